@@ -6,15 +6,36 @@ use crate::utils::bits;
 
 const MAX_TLB_SETS: u32 = 256;
 const MAX_TLB_ASSOC: u32 = 8;
-const MAX_VIRT_PAGES: u32 = 8192;
-const MAX_PHYS_PAGES: u32 = 1024;
+const MAX_STLB_SETS: u32 = 1024;
+const MAX_STLB_ASSOC: u32 = 16;
+const MAX_VIRT_PAGES: u64 = 8192;
+const MAX_PHYS_PAGES: u64 = 1024;
 const MAX_DC_SETS: u32 = 8192;
 const MAX_DC_ASSOC: u32 = 8;
 const MIN_DC_LINE_SIZE: u32 = 8;
 const MAX_L2_ASSOC: u32 = 8;
+const MAX_VICTIM_ENTRIES: u32 = 64;
+const MAX_WRITE_BUFFER_ENTRIES: u32 = 64;
+const MAX_PREFETCH_DEGREE: u32 = 16;
+const MAX_PT_LEVELS: u32 = 4;
+const MAX_WALK_CACHE_ENTRIES: u32 = 1024;
+const MAX_CORES: u32 = 16;
+const MAX_DRAM_CHANNELS: u32 = 8;
+const MAX_DRAM_RANKS: u32 = 8;
+const MAX_DRAM_BANKS: u32 = 32;
+/// Largest process ID a context switch can name, which is also its ASID
+pub const MAX_PID: u32 = 0xffff;
+/// Widest vpn Offline OPT can tell apart: the ASID goes in the bits above it
+pub const MAX_OPT_VPN_BITS: u32 = 48;
+pub const MAX_HUGE_PAGE_SIZES: usize = 2;
+const MAX_HUGE_PAGE_RANGES: usize = 8;
+/// Bytes in a page table entry of a radix page table
+pub const PTE_SIZE: u64 = 8;
 const MIN_L2_LINE_SIZE: u32 = MIN_DC_LINE_SIZE;
 #[allow(dead_code)]
 const MAX_REF_ADDR_LEN: u32 = 32;
+/// Widest address the simulator takes when the reference limits are lifted
+const MAX_ADDRESS_BITS: u32 = 64;
 
 macro_rules! error {
     ($($args:tt)*) => {{
@@ -47,39 +68,920 @@ impl AddressType {
     }
 }
 
+/// How closely a config is held to the reference's limits on geometry
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Validation {
+    /// Every maximum from the reference, so results stay comparable with it
+    Strict,
+    /// Only what the simulator itself needs (powers of 2, minimum line sizes, addresses that
+    /// fit), so modern geometries like 16-way caches or fully associative TLBs are allowed
+    Relaxed,
+}
+
+impl Validation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Strict => "strict",
+            Self::Relaxed => "relaxed",
+        }
+    }
+
+    fn from_options(ext: &ExtendedOptions) -> Result<Validation, Box<dyn Error>> {
+        let name = ext.get("", "Validation").unwrap_or("strict").to_lowercase();
+        let validation = match name.as_str() {
+            "strict" => Self::Strict,
+            "relaxed" => Self::Relaxed,
+            s => error!("Validation must be 'strict' or 'relaxed' but was '{}'", s),
+        };
+        Ok(validation)
+    }
+}
+
+/// What a write hit does: dirty the line, or also send the write on to the level below.
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum WritePolicy {
     WriteBack,
     WriteThrough,
 }
 
+impl WritePolicy {
+    /// The level's "Write policy", if it overrides the one its y/n field gave
+    fn from_options(ext: &ExtendedOptions, section: &str, default: WritePolicy) -> Result<WritePolicy, Box<dyn Error>> {
+        let policy = match ext.get(section, "Write policy").map(str::to_lowercase).as_deref() {
+            None => default,
+            Some("write-back") => Self::WriteBack,
+            Some("write-through") => Self::WriteThrough,
+            Some(s) => error!("Unknown write policy '{}' in {} configuration", s, section),
+        };
+        Ok(policy)
+    }
+}
+
+/// What a write miss does: fill the line and write into it, or pass the write on to the level
+/// below without filling anything.
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum WriteMissPolicy {
     WriteAllocate,
     NoWriteAllocate,
 }
 
+impl WriteMissPolicy {
+    /// The level's "Write miss policy", if it overrides the one its y/n field gave
+    fn from_options(ext: &ExtendedOptions, section: &str, default: WriteMissPolicy) -> Result<WriteMissPolicy, Box<dyn Error>> {
+        let policy = match ext.get(section, "Write miss policy").map(str::to_lowercase).as_deref() {
+            None => default,
+            Some("write-allocate") => Self::WriteAllocate,
+            Some("no-write-allocate") => Self::NoWriteAllocate,
+            Some(s) => error!("Unknown write miss policy '{}' in {} configuration", s, section),
+        };
+        Ok(policy)
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random { seed: u64 },
+    TreePlru,
+    Lfu,
+    Nru,
+    Srrip { rrpv_bits: u32 },
+    Brrip { rrpv_bits: u32, throttle: u32, seed: u64 },
+    Drrip { rrpv_bits: u32, throttle: u32, seed: u64, leader_sets: u32, psel_bits: u32 },
+}
+
+impl Replacement {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lru => "LRU",
+            Self::Fifo => "FIFO",
+            Self::Random { .. } => "random",
+            Self::TreePlru => "tree-PLRU",
+            Self::Lfu => "LFU",
+            Self::Nru => "NRU",
+            Self::Srrip { .. } => "SRRIP",
+            Self::Brrip { .. } => "BRRIP",
+            Self::Drrip { .. } => "DRRIP",
+        }
+    }
+
+    /// Reads the replacement policy (and any policy parameters) out of a config section for a
+    /// structure with `sets` sets. DRRIP leads with at most a quarter of the sets per policy,
+    /// so that there are always follower sets for the duel to decide.
+    fn from_options(ext: &ExtendedOptions, section: &str, sets: u32) -> Result<Replacement, Box<dyn Error>> {
+        let name = ext.get(section, "Replacement policy").unwrap_or("lru").to_lowercase();
+        let policy = match name.as_str() {
+            "lru" => Self::Lru,
+            "fifo" => Self::Fifo,
+            "random" => Self::Random { seed: ext.parse_or(section, "Random seed", 1)? },
+            "plru" | "tree-plru" => Self::TreePlru,
+            "lfu" => Self::Lfu,
+            "nru" => Self::Nru,
+            "srrip" => Self::Srrip { rrpv_bits: ext.parse_or(section, "RRPV bits", 2)? },
+            "brrip" => Self::Brrip {
+                rrpv_bits: ext.parse_or(section, "RRPV bits", 2)?,
+                throttle: ext.parse_or(section, "BRRIP throttle", 32)?,
+                seed: ext.parse_or(section, "Random seed", 1)?,
+            },
+            "drrip" => Self::Drrip {
+                rrpv_bits: ext.parse_or(section, "RRPV bits", 2)?,
+                throttle: ext.parse_or(section, "BRRIP throttle", 32)?,
+                seed: ext.parse_or(section, "Random seed", 1)?,
+                leader_sets: ext.parse_or::<u32>(section, "Leader sets", 32)?.min((sets / 4).max(1)),
+                psel_bits: ext.parse_or(section, "PSEL bits", 10)?,
+            },
+            s => error!("Unknown replacement policy '{}' in {} configuration", s, section),
+        };
+
+        match policy {
+            Self::Srrip { rrpv_bits }
+            | Self::Brrip { rrpv_bits, .. }
+            | Self::Drrip { rrpv_bits, .. } if !(1..=8).contains(&rrpv_bits) => {
+                error!("{} RRPV width is {} bits but must be between 1 and 8", section, rrpv_bits);
+            }
+            Self::Brrip { throttle: 0, .. } | Self::Drrip { throttle: 0, .. } => {
+                error!("{} BRRIP throttle must be at least 1", section);
+            }
+            Self::Drrip { leader_sets: 0, .. } => {
+                error!("{} DRRIP needs at least 1 leader set per policy", section);
+            }
+            Self::Drrip { psel_bits, .. } if !(1..=31).contains(&psel_bits) => {
+                error!("{} PSEL counter is {} bits but must be between 1 and 31", section, psel_bits);
+            }
+            _ => (),
+        }
+        Ok(policy)
+    }
+}
+
+/// A hardware prefetcher watching the demand accesses to a cache level. `degree` is how many
+/// lines each prediction fetches, and `distance` is how many lines ahead of the access the
+/// first of them is.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Prefetch {
+    NextLine { degree: u32, distance: u32 },
+    IpStride { degree: u32, distance: u32 },
+    Stream { degree: u32, distance: u32 },
+}
+
+impl Prefetch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NextLine { .. } => "next-line",
+            Self::IpStride { .. } => "IP-stride",
+            Self::Stream { .. } => "stream",
+        }
+    }
+
+    pub fn degree(&self) -> u32 {
+        match *self {
+            Self::NextLine { degree, .. } | Self::IpStride { degree, .. } | Self::Stream { degree, .. } => degree,
+        }
+    }
+
+    pub fn distance(&self) -> u32 {
+        match *self {
+            Self::NextLine { distance, .. } | Self::IpStride { distance, .. } | Self::Stream { distance, .. } => distance,
+        }
+    }
+
+    /// Reads the prefetcher (if any) out of a config section.
+    fn from_options(ext: &ExtendedOptions, section: &str) -> Result<Option<Prefetch>, Box<dyn Error>> {
+        let name = ext.get(section, "Prefetcher").unwrap_or("none").to_lowercase();
+        let degree = ext.parse_or(section, "Prefetch degree", 1)?;
+        let distance = ext.parse_or(section, "Prefetch distance", 1)?;
+        let prefetch = match name.as_str() {
+            "none" => return Ok(None),
+            "next-line" => Self::NextLine { degree, distance },
+            "ip-stride" => Self::IpStride { degree, distance },
+            "stream" => Self::Stream { degree, distance },
+            s => error!("Unknown prefetcher '{}' in {} configuration", s, section),
+        };
+
+        if !(1..=MAX_PREFETCH_DEGREE).contains(&degree) {
+            error!("{} prefetch degree is {} but must be between 1 and {}", section, degree, MAX_PREFETCH_DEGREE);
+        }
+        if distance == 0 {
+            error!("{} prefetch distance must be at least 1 line", section);
+        }
+        Ok(Some(prefetch))
+    }
+}
+
+/// How a cache level's contents relate to those of the level above it.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Inclusion {
+    /// What a level does when no policy is named, as it did before there were policies:
+    /// evicting a line invalidates only the copies above that were filled from the same address
+    Legacy,
+    /// Everything above is also here: evicting a line invalidates it above (back-invalidation)
+    Inclusive,
+    /// Nothing above is also here: the level is filled only by lines evicted from above, and a
+    /// hit moves the line up
+    Exclusive,
+    /// Non-inclusive non-exclusive: filled on a miss like an inclusive level, but evictions
+    /// leave the level above alone
+    Nine,
+}
+
+impl Inclusion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Legacy | Self::Inclusive => "inclusive",
+            Self::Exclusive => "exclusive",
+            Self::Nine => "non-inclusive non-exclusive",
+        }
+    }
+
+    fn from_options(ext: &ExtendedOptions, section: &str) -> Result<Inclusion, Box<dyn Error>> {
+        let name = match ext.get(section, "Inclusion policy") {
+            Some(name) => name.to_lowercase(),
+            None => return Ok(Self::Legacy),
+        };
+        let inclusion = match name.as_str() {
+            "inclusive" => Self::Inclusive,
+            "exclusive" => Self::Exclusive,
+            "nine" => Self::Nine,
+            s => error!("Unknown inclusion policy '{}' in {} configuration", s, section),
+        };
+        Ok(inclusion)
+    }
+}
+
+/// The state machine keeping the cores' data caches coherent.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum CoherenceProtocol {
+    Mesi,
+    /// MESI plus Owned: a dirty line can be shared, with its owner answering for it
+    Moesi,
+    /// MESI plus Forward: one clean shared copy answers misses instead of the shared cache
+    Mesif,
+}
+
+impl CoherenceProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mesi => "MESI",
+            Self::Moesi => "MOESI",
+            Self::Mesif => "MESIF",
+        }
+    }
+}
+
+/// How the cores' data caches are kept coherent, when there is more than one.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct CoherenceConfig {
+    pub protocol: CoherenceProtocol,
+    /// Requests go to a full-map directory at the shared cache rather than being broadcast
+    pub directory: bool,
+}
+
+impl CoherenceConfig {
+    /// Every protocol, snooping and with a directory.
+    pub fn all() -> impl Iterator<Item = CoherenceConfig> {
+        [CoherenceProtocol::Mesi, CoherenceProtocol::Moesi, CoherenceProtocol::Mesif].into_iter()
+            .flat_map(|protocol| [false, true].map(|directory| CoherenceConfig { protocol, directory }))
+    }
+
+    /// "MESI snooping", "MOESI directory", ...
+    pub fn name(&self) -> String {
+        format!("{} {}", self.protocol.as_str(), if self.directory { "directory" } else { "snooping" })
+    }
+
+    fn from_options(ext: &ExtendedOptions) -> Result<CoherenceConfig, Box<dyn Error>> {
+        let name = ext.get("", "Coherence protocol").unwrap_or("mesi").to_lowercase();
+        let protocol = match name.as_str() {
+            "mesi" => CoherenceProtocol::Mesi,
+            "moesi" => CoherenceProtocol::Moesi,
+            "mesif" => CoherenceProtocol::Mesif,
+            s => error!("Unknown coherence protocol '{}'", s),
+        };
+        let directory = match ext.get("", "Coherence tracking").unwrap_or("snooping").to_lowercase().as_str() {
+            "snooping" => false,
+            "directory" => true,
+            s => error!("Coherence tracking must be 'snooping' or 'directory' but was '{}'", s),
+        };
+        Ok(CoherenceConfig { protocol, directory })
+    }
+}
+
+/// Cycles each part of the hierarchy takes, for timing every access.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct LatencyConfig {
+    pub tlb: u64,
+    pub stlb: u64,
+    /// A page table walk, on top of the PTE reads of a radix page table (which cost whatever
+    /// reading them from the caches or memory does)
+    pub walk: u64,
+    /// One per configured cache level, closest to the CPU first
+    pub caches: Vec<u64>,
+    pub memory: u64,
+    /// Reading a page in, or writing a dirty one out
+    pub disk: u64,
+}
+
+impl LatencyConfig {
+    /// The section each cache level's latency is given under
+    fn cache_section(level: usize) -> String {
+        match level {
+            0 => "Data Cache".to_string(),
+            _ => format!("L{} Cache", level + 1),
+        }
+    }
+
+    /// The latencies, if any are given. The rest take typical values: 1 cycle for the TLB and
+    /// DC, 10 for L2, 40 for each level below it and 100 for memory.
+    fn from_options(ext: &ExtendedOptions, levels: usize) -> Result<Option<LatencyConfig>, Box<dyn Error>> {
+        let cache_sections: Vec<String> = (0..levels).map(Self::cache_section).collect();
+        let given = [("Data TLB", "Latency"), ("STLB", "Latency"), ("Page Table", "Walk latency"),
+                ("", "Memory latency"), ("", "Disk latency")].into_iter()
+            .chain(cache_sections.iter().map(|section| (section.as_str(), "Latency")))
+            .any(|(section, key)| ext.get(section, key).is_some());
+        if !given {
+            return Ok(None);
+        }
+
+        let mut caches = Vec::with_capacity(levels);
+        for (level, section) in cache_sections.iter().enumerate() {
+            let default = match level {
+                0 => 1,
+                1 => 10,
+                _ => 40,
+            };
+            caches.push(ext.parse_or(section, "Latency", default)?);
+        }
+        Ok(Some(LatencyConfig {
+            tlb: ext.parse_or("Data TLB", "Latency", 1)?,
+            stlb: ext.parse_or("STLB", "Latency", 8)?,
+            walk: ext.parse_or("Page Table", "Walk latency", 20)?,
+            caches,
+            memory: ext.parse_or("", "Memory latency", 100)?,
+            disk: ext.parse_or("", "Disk latency", 100_000)?,
+        }))
+    }
+}
+
+/// How a physical address is split into DRAM coordinates, from the top bits down.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum AddressMapping {
+    /// row, rank, bank, channel, column: a whole row of consecutive addresses stays in one bank
+    RowBankColumn,
+    /// row, high column, rank, bank, channel, low column: consecutive lines go to different
+    /// channels and banks
+    RowColumnBank,
+    /// Like `RowBankColumn`, with the bank XORed with the low bits of the row so that rows that
+    /// would conflict in one bank spread over all of them
+    Permutation,
+}
+
+impl AddressMapping {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RowBankColumn => "row:rank:bank:channel:column",
+            Self::RowColumnBank => "row:column:rank:bank:channel",
+            Self::Permutation => "permutation-based",
+        }
+    }
+}
+
+/// What a bank does with its row buffer after an access.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum RowBufferPolicy {
+    /// Leave the row open, betting the next access to the bank hits it
+    Open,
+    /// Precharge straight away, so no access hits or conflicts
+    Closed,
+}
+
+impl RowBufferPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+/// A DRAM main memory, which gives every request to memory a location and a timing.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub struct DramConfig {
+    pub channels: u32,
+    pub ranks: u32,
+    /// Banks in each rank
+    pub banks: u32,
+    /// Bytes in one row of a bank
+    pub row_size: u32,
+    pub mapping: AddressMapping,
+    pub policy: RowBufferPolicy,
+    /// log2 of the bytes in a request (a line of the last cache level), which the
+    /// `RowColumnBank` mapping interleaves by
+    pub line_offset: u32,
+    /// Cycles to read a column from an open row, to open a row, and to close one
+    pub t_cas: u64,
+    pub t_rcd: u64,
+    pub t_rp: u64,
+}
+
+impl DramConfig {
+    /// The DRAM, if there is a "DRAM configuration" section. Requests are lines of
+    /// `line_size` bytes, and every coordinate has to fit in the `physical_bits` of an address.
+    fn from_options(ext: &ExtendedOptions, line_size: u32, physical_bits: u32) -> Result<Option<DramConfig>, Box<dyn Error>> {
+        let section = "DRAM";
+        if !ext.has_section(section) {
+            return Ok(None);
+        }
+        let channels = ext.parse_or::<u32>(section, "Channels", 1)?;
+        let ranks = ext.parse_or::<u32>(section, "Ranks", 1)?;
+        let banks = ext.parse_or::<u32>(section, "Banks", 8)?;
+        let row_size = ext.parse_or::<u32>(section, "Row size", 2048)?;
+        for (name, n, max) in [("channels", channels, MAX_DRAM_CHANNELS), ("ranks", ranks, MAX_DRAM_RANKS), ("banks", banks, MAX_DRAM_BANKS)] {
+            if !bits::is_pow2(n) || n > max {
+                error!("DRAM has {} {} but must have a power of 2 up to {}", n, name, max);
+            }
+        }
+        if !bits::is_pow2(row_size) || row_size < line_size {
+            error!("DRAM row size is {} but must be a power of 2 of at least the line size ({})", row_size, line_size);
+        }
+        let used_bits = bits::min_repr(channels) + bits::min_repr(ranks) + bits::min_repr(banks) + bits::min_repr(row_size);
+        if used_bits > physical_bits {
+            error!("DRAM channels, ranks, banks and rows take {} address bits but physical addresses only have {}",
+                used_bits, physical_bits);
+        }
+
+        let mapping = match ext.get(section, "Address mapping").unwrap_or("row-bank-column").to_lowercase().as_str() {
+            "row-bank-column" => AddressMapping::RowBankColumn,
+            "row-column-bank" => AddressMapping::RowColumnBank,
+            "permutation" => AddressMapping::Permutation,
+            s => error!("Unknown DRAM address mapping '{}'", s),
+        };
+        let policy = match ext.get(section, "Row buffer policy").unwrap_or("open").to_lowercase().as_str() {
+            "open" => RowBufferPolicy::Open,
+            "closed" => RowBufferPolicy::Closed,
+            s => error!("DRAM row buffer policy must be 'open' or 'closed' but was '{}'", s),
+        };
+
+        Ok(Some(DramConfig {
+            channels,
+            ranks,
+            banks,
+            row_size,
+            mapping,
+            policy,
+            line_offset: bits::min_repr(line_size),
+            t_cas: ext.parse_or(section, "tCAS", 40)?,
+            t_rcd: ext.parse_or(section, "tRCD", 40)?,
+            t_rp: ext.parse_or(section, "tRP", 40)?,
+        }))
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum PageReplacement {
+    Lru,
+    Fifo,
+    Clock,
+    EnhancedSecondChance,
+    Aging { counter_bits: u32, interval: u32 },
+    WsClock { window: u32 },
+}
+
+impl PageReplacement {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lru => "LRU",
+            Self::Fifo => "FIFO",
+            Self::Clock => "clock",
+            Self::EnhancedSecondChance => "enhanced second chance",
+            Self::Aging { .. } => "aging",
+            Self::WsClock { .. } => "WSClock",
+        }
+    }
+
+    fn from_options(ext: &ExtendedOptions) -> Result<PageReplacement, Box<dyn Error>> {
+        let section = "Page Table";
+        let name = ext.get(section, "Replacement policy").unwrap_or("lru").to_lowercase();
+        let policy = match name.as_str() {
+            "lru" => Self::Lru,
+            "fifo" => Self::Fifo,
+            "clock" | "second-chance" => Self::Clock,
+            "esc" | "enhanced-second-chance" => Self::EnhancedSecondChance,
+            "aging" => Self::Aging {
+                counter_bits: ext.parse_or(section, "Aging counter bits", 8)?,
+                interval: ext.parse_or(section, "Aging interval", 1)?,
+            },
+            "wsclock" => Self::WsClock { window: ext.parse_or(section, "Working set window", 1000)? },
+            s => error!("Unknown page replacement policy '{}'", s),
+        };
+
+        match policy {
+            Self::Aging { counter_bits, .. } if !(1..=32).contains(&counter_bits) => {
+                error!("Aging counter is {} bits but must be between 1 and 32", counter_bits);
+            }
+            Self::Aging { interval: 0, .. } => {
+                error!("Aging interval must be at least 1 reference");
+            }
+            _ => (),
+        }
+        Ok(policy)
+    }
+}
+
+/// How the page table picks a free frame for a page, which decides the cache sets the page's
+/// lines land in.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum FrameAllocation {
+    /// Lowest frame first
+    Sequential,
+    /// Any free frame, uniformly at random
+    Random { seed: u64 },
+    /// Each fault takes the next color after the last one handed out
+    BinHopping,
+    /// A page gets a frame of the same color as its virtual page, or of its process's own colors
+    /// when they are partitioned
+    PageColoring { colors_per_process: Option<u64> },
+}
+
+impl FrameAllocation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sequential => "sequential",
+            Self::Random { .. } => "random",
+            Self::BinHopping => "bin hopping",
+            Self::PageColoring { .. } => "page coloring",
+        }
+    }
+
+    fn from_options(ext: &ExtendedOptions, colors: u64) -> Result<FrameAllocation, Box<dyn Error>> {
+        let section = "Page Table";
+        let name = ext.get(section, "Frame allocation").unwrap_or("sequential").to_lowercase();
+        let allocation = match name.as_str() {
+            "sequential" => Self::Sequential,
+            "random" => Self::Random { seed: ext.parse_or(section, "Random seed", 1)? },
+            "bin-hopping" => Self::BinHopping,
+            "page-coloring" => Self::PageColoring {
+                colors_per_process: match ext.get(section, "Colors per process") {
+                    Some(_) => Some(ext.parse::<u64>(section, "Colors per process")?),
+                    None => None,
+                },
+            },
+            s => error!("Unknown frame allocation policy '{}'", s),
+        };
+
+        if let Self::PageColoring { colors_per_process: Some(n) } = allocation {
+            if !(1..=colors).contains(&n) {
+                error!("Each process gets {} page colors but there are only {}", n, colors);
+            }
+        }
+        Ok(allocation)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct TLBConfig {
     pub sets: u32,
     pub set_entries: u32,
     pub idx_size: u32,
     pub offset_size: u32,
+    pub replacement: Replacement,
     pub enabled: bool,
 }
 
+/// Shape of a hierarchical (radix) page table.
+#[derive(Copy, Clone, Debug)]
+pub struct RadixConfig {
+    pub levels: u32,
+    /// Index bits at each level, root first (only the first `levels` are used)
+    pub bits: [u32; MAX_PT_LEVELS as usize],
+    /// Physical address the page table pages start at, just past the end of physical memory
+    pub base_addr: u64,
+    /// Walks read their PTEs through the caches rather than straight from memory
+    pub cached: bool,
+    /// Page walk cache for the entries of each upper level, root first. Each is shaped like a
+    /// TLB whose "pages" are the vpn bits down to and including that level.
+    pub walk_caches: [Option<TLBConfig>; MAX_PT_LEVELS as usize - 1],
+}
+
+impl RadixConfig {
+    fn from_options(ext: &ExtendedOptions, vpn_bits: u32, base_addr: u64, address_bits: u32)
+        -> Result<Option<RadixConfig>, Box<dyn Error>>
+    {
+        let section = "Page Table";
+        let levels = ext.parse_or::<u32>(section, "Page table levels", 1)?;
+        if levels == 1 {
+            return Ok(None);
+        }
+        if !(2..=MAX_PT_LEVELS).contains(&levels) {
+            error!("A radix page table has {} levels but must have between 2 and {}", levels, MAX_PT_LEVELS);
+        }
+
+        let mut bits = [0; MAX_PT_LEVELS as usize];
+        match ext.get(section, "Bits per level") {
+            Some(list) => {
+                let given = list.split(',').map(|b| b.parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+                if given.len() != levels as usize {
+                    error!("Bits per level lists {} levels but the page table has {}", given.len(), levels);
+                }
+                bits[..given.len()].copy_from_slice(&given);
+            },
+            // Split the vpn as evenly as possible, with any extra bits going to the upper levels
+            None => for (level, b) in bits.iter_mut().take(levels as usize).enumerate() {
+                *b = vpn_bits / levels + ((level as u32) < vpn_bits % levels) as u32;
+            },
+        }
+        let total: u32 = bits.iter().sum();
+        if total != vpn_bits {
+            error!("Bits per level add up to {} but the virtual page number has {} bits", total, vpn_bits);
+        }
+        if bits[..levels as usize].contains(&0) {
+            error!("Every page table level needs at least 1 index bit");
+        }
+
+        // Every table at every level would need to fit in the address space (each aligned to its size)
+        let mut worst = base_addr as u128;
+        let mut prefix_bits = 0;
+        for &b in bits[..levels as usize].iter() {
+            let table_size = (1u128 << b) * PTE_SIZE as u128;
+            worst += (1u128 << prefix_bits) * table_size + table_size;
+            prefix_bits += b;
+        }
+        if worst > 1u128 << address_bits {
+            error!("The radix page table doesn't fit in the {}-bit physical address space above physical memory",
+                address_bits);
+        }
+
+        Ok(Some(RadixConfig {
+            levels,
+            bits,
+            base_addr,
+            cached: ext.parse_yn_or(section, "Page walk through caches", false)?,
+            walk_caches: RadixConfig::walk_caches_from_options(ext, levels)?,
+        }))
+    }
+
+    /// Reads the entries and associativity of the page walk cache for each upper level. Both
+    /// are comma-separated lists, root first; 0 entries means that level isn't cached, and the
+    /// caches are fully associative unless told otherwise.
+    fn walk_caches_from_options(ext: &ExtendedOptions, levels: u32)
+        -> Result<[Option<TLBConfig>; MAX_PT_LEVELS as usize - 1], Box<dyn Error>>
+    {
+        let section = "Page Table";
+        let mut walk_caches = [None; MAX_PT_LEVELS as usize - 1];
+        let upper_levels = levels as usize - 1;
+        let list = |key: &str| -> Result<Option<Vec<u32>>, Box<dyn Error>> {
+            match ext.get(section, key) {
+                Some(list) => {
+                    let given = list.split(',').map(|n| n.parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+                    if given.len() != upper_levels {
+                        error!("{} lists {} levels but the page table has {} upper levels", key, given.len(), upper_levels);
+                    }
+                    Ok(Some(given))
+                },
+                None => Ok(None),
+            }
+        };
+
+        let entries = match list("Walk cache entries")? {
+            Some(entries) => entries,
+            None => return Ok(walk_caches),
+        };
+        let assocs = list("Walk cache associativity")?;
+        for (level, &entries) in entries.iter().enumerate() {
+            if entries == 0 {
+                continue;
+            }
+            let set_entries = assocs.as_ref().map_or(entries, |a| a[level]);
+            if entries > MAX_WALK_CACHE_ENTRIES {
+                error!("Walk cache for level {} has {} entries but max is {}", level + 1, entries, MAX_WALK_CACHE_ENTRIES);
+            }
+            if set_entries == 0 || entries % set_entries != 0 || !bits::is_pow2(entries / set_entries) {
+                error!("Walk cache for level {} has {} entries, which can't make a power of 2 sets of {}",
+                    level + 1, entries, set_entries);
+            }
+            let sets = entries / set_entries;
+            walk_caches[level] = Some(TLBConfig {
+                sets,
+                set_entries,
+                idx_size: bits::min_repr(sets),
+                offset_size: 0,
+                replacement: Replacement::Lru,
+                enabled: true,
+            });
+        }
+        Ok(walk_caches)
+    }
+}
+
+/// One huge page size, and the physical frames set aside for pages of that size.
+#[derive(Copy, Clone, Debug)]
+pub struct HugePageSize {
+    pub size: u64,
+    /// log2 of the number of base pages in one huge page
+    pub shift: u32,
+    /// Huge pages that fit in the frames reserved for this size
+    pub frames: u32,
+}
+
+/// A range of virtual addresses that is always mapped with huge pages of one size.
+#[derive(Copy, Clone, Debug)]
+pub struct HugePageRange {
+    pub start: u64,
+    /// Last address in the range
+    pub end: u64,
+    /// Index into the huge page sizes
+    pub size: usize,
+}
+
+/// Pages bigger than the base page size. Each size has its own pool of physical frames (like
+/// hugetlbfs), carved out of the top of physical memory, and is used either for the configured
+/// ranges of virtual addresses or for regions promoted once enough of their base pages are in
+/// memory.
+#[derive(Copy, Clone, Debug)]
+pub struct HugePageConfig {
+    /// Smallest first (unused sizes are `None`)
+    pub sizes: [Option<HugePageSize>; MAX_HUGE_PAGE_SIZES],
+    pub ranges: [Option<HugePageRange>; MAX_HUGE_PAGE_RANGES],
+    /// Percentage of a huge page's base pages that must be in memory before a fault in it
+    /// promotes the whole region
+    pub promotion_threshold: Option<u32>,
+    /// Base page frames left over for base pages
+    pub base_frames: u64,
+}
+
+impl HugePageConfig {
+    fn from_options(ext: &ExtendedOptions, page_size: u64, physical_pages: u64, max_virtual_addr: u64)
+        -> Result<Option<HugePageConfig>, Box<dyn Error>>
+    {
+        let section = "Page Table";
+        let list = |key: &str| ext.get(section, key).map(|list| list.split(',').map(str::to_owned).collect::<Vec<_>>());
+
+        let given_sizes = match list("Huge page sizes") {
+            Some(sizes) => sizes,
+            None => return Ok(None),
+        };
+        let given_frames = list("Huge page frames").unwrap_or_default();
+        if given_sizes.len() > MAX_HUGE_PAGE_SIZES {
+            error!("{} huge page sizes are listed but max is {}", given_sizes.len(), MAX_HUGE_PAGE_SIZES);
+        }
+        if given_frames.len() != given_sizes.len() {
+            error!("Huge page frames lists {} sizes but there are {} huge page sizes", given_frames.len(), given_sizes.len());
+        }
+
+        let mut sizes = [None; MAX_HUGE_PAGE_SIZES];
+        let mut reserved: u64 = 0;
+        for (i, (size, frames)) in given_sizes.iter().zip(given_frames.iter()).enumerate() {
+            let (size, frames) = (size.parse::<u64>()?, frames.parse::<u32>()?);
+            if !bits::is_pow2(size) || size <= page_size {
+                error!("Huge page size is {} but must be a power of 2 bigger than the page size", size);
+            }
+            if size > max_virtual_addr {
+                error!("Huge page size is {} but the virtual address space is only {} bytes", size, max_virtual_addr);
+            }
+            if i > 0 && matches!(sizes[i - 1], Some(HugePageSize { size: smaller, .. }) if smaller >= size) {
+                error!("Huge page sizes must be listed smallest first");
+            }
+            if frames == 0 {
+                error!("Huge pages of {} bytes need at least 1 frame", size);
+            }
+            let shift = bits::min_repr(size) - bits::min_repr(page_size);
+            reserved += (frames as u64) << shift;
+            sizes[i] = Some(HugePageSize { size, shift, frames });
+        }
+        if reserved >= physical_pages {
+            error!("Huge page frames take up {} pages but there are only {} physical pages", reserved, physical_pages);
+        }
+
+        let mut ranges = [None; MAX_HUGE_PAGE_RANGES];
+        let given_ranges = list("Huge page ranges").unwrap_or_default();
+        if given_ranges.len() > MAX_HUGE_PAGE_RANGES {
+            error!("{} huge page ranges are listed but max is {}", given_ranges.len(), MAX_HUGE_PAGE_RANGES);
+        }
+        for (i, range) in given_ranges.iter().enumerate() {
+            // <start>-<end>=<page size>, with the addresses in hex
+            let (bounds, size) = range.split_once('=').ok_or(format!("Huge page range {} has no page size", range))?;
+            let (start, end) = bounds.split_once('-').ok_or(format!("Huge page range {} has no end", range))?;
+            let (start, end, size) = (u64::from_str_radix(start, 16)?, u64::from_str_radix(end, 16)?, size.parse::<u64>()?);
+            let size = match sizes.iter().flatten().position(|s| s.size == size) {
+                Some(i) => i,
+                None => error!("Huge page range {} uses {}-byte pages, which isn't a huge page size", range, size),
+            };
+            let page = sizes[size].map_or(0, |s| s.size);
+            if start > end || start % page != 0 || (end as u128 + 1) % page as u128 != 0 {
+                error!("Huge page range {} must start and end on {}-byte page boundaries", range, page);
+            }
+            if end >= max_virtual_addr {
+                error!("Huge page range {} is past the end of the virtual address space", range);
+            }
+            if ranges.iter().flatten().any(|r: &HugePageRange| start <= r.end && r.start <= end) {
+                error!("Huge page range {} overlaps another range", range);
+            }
+            ranges[i] = Some(HugePageRange { start, end, size });
+        }
+
+        let promotion_threshold = match ext.get(section, "Promotion threshold") {
+            Some(_) => Some(ext.parse::<u32>(section, "Promotion threshold")?),
+            None => None,
+        };
+        if matches!(promotion_threshold, Some(t) if !(1..=100).contains(&t)) {
+            error!("Promotion threshold must be a percentage between 1 and 100");
+        }
+
+        Ok(Some(HugePageConfig {
+            sizes,
+            ranges,
+            promotion_threshold,
+            base_frames: physical_pages - reserved,
+        }))
+    }
+
+    /// The first base frame of the pool for each huge page size, which sit one after another
+    /// past the base page frames.
+    pub fn pool_start(&self, size: usize) -> u64 {
+        self.base_frames + self.sizes[..size].iter().flatten().map(|s| (s.frames as u64) << s.shift).sum::<u64>()
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct PageTableConfig {
-    pub virtual_pages: u32,
-    pub physical_pages: u32,
-    pub max_physical_addr: u32,
-    pub max_virtual_addr: u32,
-    pub page_size: u32,
+    pub virtual_pages: u64,
+    pub physical_pages: u64,
+    pub max_physical_addr: u64,
+    pub max_virtual_addr: u64,
+    pub page_size: u64,
     pub idx_size: u32,
     pub offset_size: u32,
+    pub replacement: PageReplacement,
+    /// Hierarchical page table to walk on a TLB miss, if not flat
+    pub radix: Option<RadixConfig>,
+    /// Pages bigger than `page_size`, if any
+    pub huge: Option<HugePageConfig>,
+    /// Base pages of swap space that dirty pages are written out to, if it is modeled
+    pub swap_pages: Option<u64>,
+    pub frame_allocation: FrameAllocation,
+    /// Page colors: pages whose frames map to the same DC and L2 sets share a color
+    pub colors: u64,
     pub enabled: bool, // disabled if input is physical addresses
 }
 
+/// When the write buffer sends its oldest line on to the level below the DC.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum DrainPolicy {
+    /// One line after every read that leaves the level below idle
+    Eager,
+    /// After an access, for as long as this many lines or more are waiting
+    Threshold(u32),
+    /// Only when a write finds the buffer full, which stalls it
+    Full,
+}
+
+impl DrainPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Eager => "eager",
+            Self::Threshold(_) => "threshold",
+            Self::Full => "full",
+        }
+    }
+}
+
+/// A FIFO of lines written through the DC, waiting to be written to the level below. Writes to
+/// a line that is already waiting are combined with it.
+#[derive(Copy, Clone, Debug)]
+pub struct WriteBufferConfig {
+    pub entries: u32,
+    pub drain: DrainPolicy,
+}
+
+impl WriteBufferConfig {
+    fn from_options(ext: &ExtendedOptions, dc: &CacheConfig) -> Result<Option<WriteBufferConfig>, Box<dyn Error>> {
+        let section = "Data Cache";
+        let entries = match ext.parse_or::<u32>(section, "Write buffer entries", 0)? {
+            0 => return Ok(None),
+            entries if entries > MAX_WRITE_BUFFER_ENTRIES => {
+                error!("Write buffer has {} entries but max is {}", entries, MAX_WRITE_BUFFER_ENTRIES);
+            }
+            entries => entries,
+        };
+        if dc.write_policy != WritePolicy::WriteThrough {
+            error!("A write buffer needs a write-through data cache");
+        }
+
+        let name = ext.get(section, "Write buffer drain").unwrap_or("eager").to_lowercase();
+        let drain = match name.as_str() {
+            "eager" => DrainPolicy::Eager,
+            "threshold" => DrainPolicy::Threshold(ext.parse_or(section, "Drain threshold", (entries + 1) / 2)?),
+            "full" => DrainPolicy::Full,
+            s => error!("Unknown write buffer drain policy '{}'", s),
+        };
+        if let DrainPolicy::Threshold(threshold) = drain {
+            if !(1..=entries).contains(&threshold) {
+                error!("Drain threshold is {} entries but must be between 1 and {}", threshold, entries);
+            }
+        }
+        Ok(Some(WriteBufferConfig { entries, drain }))
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct CacheConfig {
     pub sets: u32,
@@ -89,89 +991,200 @@ pub struct CacheConfig {
     pub offset_size: u32,
     pub write_miss_policy: WriteMissPolicy,
     pub write_policy: WritePolicy,
+    pub replacement: Replacement,
+    /// Relationship to the level above (meaningless for the DC)
+    pub inclusion: Inclusion,
+    pub prefetch: Option<Prefetch>,
     pub enabled: bool,
 
     pub id: u8,
 }
 
-#[derive(Copy, Clone, Debug)]
+impl CacheConfig {
+    /// What the level is called in the output: "DC" for the first level, then "L2", "L3", ...
+    /// (and "VC" for the DC's victim cache)
+    pub fn name(&self) -> String {
+        match self.id {
+            0 => "VC".to_string(),
+            1 => "DC".to_string(),
+            id => format!("L{}", id),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Config {
     pub tlb: TLBConfig,
+    /// Second-level TLB, probed when the data TLB misses
+    pub stlb: Option<TLBConfig>,
     pub pt: PageTableConfig,
-    pub dc: CacheConfig,
-    pub l2: CacheConfig,
+    /// Every configured level of cache, closest to the CPU first. The first is always the DC
+    /// and the second is always L2 (which may be disabled).
+    pub caches: Vec<CacheConfig>,
+    /// Fully associative cache of lines evicted from the DC, probed on a DC miss
+    pub victim_cache: Option<CacheConfig>,
+    /// Buffer for the writes a write-through DC sends on to the level below
+    pub write_buffer: Option<WriteBufferConfig>,
     pub address_type: AddressType,
+    /// Width of every address (32 is the reference format, and keeps its limits)
+    pub address_bits: u32,
+    /// Whether the reference's maximums on geometry are enforced
+    pub validation: Validation,
+    /// Cores sharing the L2 and below, each with its own data TLB (and STLB) and data cache
+    pub cores: u32,
+    pub coherence: CoherenceConfig,
+    /// Re-run the trace with every coherence protocol and report the traffic of each
+    pub compare_protocols: bool,
+    /// Flush the TLBs (and walk caches) on a context switch instead of tagging entries with ASIDs
+    pub flush_tlb_on_switch: bool,
+    /// Time every access with these latencies, if any are given
+    pub latency: Option<LatencyConfig>,
+    /// Model main memory as DRAM banks with row buffers, if it has a section
+    pub dram: Option<DramConfig>,
+    /// Re-run the trace with Belady's OPT replacement and report the difference
+    pub offline_opt: bool,
+    /// Add writeback columns to the output table and writeback totals to the statistics
+    pub report_writebacks: bool,
+    /// Add back-invalidation and exclusive swap totals to the statistics (whenever any level
+    /// names an inclusion policy)
+    pub report_inclusion: bool,
 }
 
 impl Config {
     pub fn from_file(path: &str) -> Result<Config, Box<dyn Error>> {
         let file = File::open(path)?;
 
+        // A line that can't be read is skipped rather than ending the config
+        #[allow(clippy::lines_filter_map_ok)]
         let lines = BufReader::new(file)
             .lines()
             .filter_map(|line| line.ok())
-            .filter(|line| !line.is_empty() && line.contains(':'));
+            .filter(|line| !line.trim().is_empty());
 
-        // Assume config file is always in correct order
-        let opts = {
+        // Assume config file is always in correct order (apart from the extended options)
+        let (opts, ext) = {
             let mut fields = Vec::<String>::new();
+            let mut ext = ExtendedOptions::default();
+            let mut section = String::new();
             for line in lines {
                 if let Some(idx) = line.find(':') {
-                    let (_left_field, right_field) = line.split_at(idx + 1);
+                    let (left_field, right_field) = line.split_at(idx + 1);
+                    let key = left_field.trim_end_matches(':').trim();
                     let mut field = right_field.to_owned();
                     field.retain(|c| !c.is_whitespace());
-                    fields.push(field);
+                    if ExtendedOptions::is_extended(key) || ExtendedOptions::is_extended_section(&section, key) {
+                        ext.push(&section, key, field);
+                    } else {
+                        fields.push(field);
+                    }
+                } else {
+                    section = line.trim().to_owned();
                 }
             }
-            fields
+            (fields, ext)
         };
 
         if opts.len() != 16 {
             error!("Expected 16 configuration parameters, found {}.", opts.len());
         }
+        let validation = Validation::from_options(&ext)?;
+        let strict = validation == Validation::Strict;
 
         let tlb_config = {
             let sets = opts[0].parse::<u32>()?;
             let set_entries = opts[1].parse::<u32>()?;
             let idx_size   = bits::min_repr(sets);
-            let _page_size = opts[4].parse::<u32>()?;
-            let offset_size = bits::min_repr(_page_size as u32) as u32;
+            let _page_size = opts[4].parse::<u64>()?;
+            let offset_size = bits::min_repr(_page_size);
 		    let enabled = opts[14] == "y";
             
-            if sets > MAX_TLB_SETS {
+            if strict && sets > MAX_TLB_SETS {
                 error!("{} TLB sets specified but max is {}.", sets, MAX_TLB_SETS);
             }
-            if set_entries > MAX_TLB_ASSOC {
+            if strict && set_entries > MAX_TLB_ASSOC {
                 error!("TLB has associativity of {} but max is {}.", set_entries, MAX_TLB_ASSOC);
             }
             if set_entries.count_ones() != 1 {
                 error!("TLB associativity is {} but must be a power of 2", set_entries);
             }
 
-            TLBConfig { sets, set_entries, idx_size, offset_size, enabled }
+            let replacement = Replacement::from_options(&ext, "Data TLB", sets)?;
+
+            TLBConfig { sets, set_entries, idx_size, offset_size, replacement, enabled }
+        };
+
+        // The second-level TLB only exists if it has a "STLB configuration" section
+        let stlb_config = if ext.has_section("STLB") {
+            let section = "STLB";
+            let sets = ext.parse::<u32>(section, "Number of sets")?;
+            let set_entries = ext.parse::<u32>(section, "Set size")?;
+
+            if !bits::is_pow2(sets) {
+                error!("STLB has {} sets but must be a power of 2", sets);
+            }
+            if strict && sets > MAX_STLB_SETS {
+                error!("STLB has {} sets but max is {}", sets, MAX_STLB_SETS);
+            }
+            if strict && set_entries > MAX_STLB_ASSOC {
+                error!("STLB has associativity of {} but max is {}", set_entries, MAX_STLB_ASSOC);
+            }
+            if set_entries.count_ones() != 1 {
+                error!("STLB associativity is {} but must be a power of 2", set_entries);
+            }
+
+            Some(TLBConfig {
+                sets,
+                set_entries,
+                idx_size: bits::min_repr(sets),
+                offset_size: tlb_config.offset_size,
+                replacement: Replacement::from_options(&ext, section, sets)?,
+                enabled: tlb_config.enabled,
+            })
+        } else {
+            None
         };
 
 
+        // Anything wider than the reference's 32 bits (or relaxed validation) lifts its limits on
+        // the page counts
+        let address_bits = ext.parse_or::<u32>("", "Address bits", 32)?;
+        if !(32..=MAX_ADDRESS_BITS).contains(&address_bits) {
+            error!("Addresses are {} bits but must be between 32 and {} bits", address_bits, MAX_ADDRESS_BITS);
+        }
+
         let pt_config = {
-            let virtual_pages = opts[2].parse::<u32>()?;
-            let physical_pages = opts[3].parse::<u32>()?;
-            let page_size = opts[4].parse::<u32>()?;
-            let max_physical_addr = physical_pages * page_size;
-            let max_virtual_addr = virtual_pages * page_size;
-            let idx_size = bits::min_repr(virtual_pages as u32) as u32;
-            let offset_size = bits::min_repr(page_size as u32) as u32;
+            let virtual_pages = opts[2].parse::<u64>()?;
+            let physical_pages = opts[3].parse::<u64>()?;
+            let page_size = opts[4].parse::<u64>()?;
+            let max_physical_addr = physical_pages.checked_mul(page_size)
+                .ok_or("Physical memory is too big")?;
+            let max_virtual_addr = virtual_pages.checked_mul(page_size)
+                .ok_or("The virtual address space is too big")?;
+            let idx_size = bits::min_repr(virtual_pages);
+            let offset_size = bits::min_repr(page_size);
             let enabled = parse_yn!(opts, 13);
 
-            if virtual_pages > MAX_VIRT_PAGES {
-                error!("The number of virtual pages is {} but max is {}.", virtual_pages, MAX_VIRT_PAGES);
-            }
-            if physical_pages > MAX_PHYS_PAGES {
-                error!("The number of physical pages is {} but max is {}.", physical_pages, MAX_PHYS_PAGES);
+            if strict && address_bits == 32 {
+                if virtual_pages > MAX_VIRT_PAGES {
+                    error!("The number of virtual pages is {} but max is {}.", virtual_pages, MAX_VIRT_PAGES);
+                }
+                if physical_pages > MAX_PHYS_PAGES {
+                    error!("The number of physical pages is {} but max is {}.", physical_pages, MAX_PHYS_PAGES);
+                }
+            } else {
+                if max_virtual_addr as u128 > 1u128 << address_bits {
+                    error!("The virtual address space is {} bytes but {}-bit addresses only cover {}",
+                        max_virtual_addr, address_bits, 1u128 << address_bits);
+                }
+                if max_physical_addr as u128 > 1u128 << address_bits {
+                    error!("Physical memory is {} bytes but {}-bit addresses only cover {}",
+                        max_physical_addr, address_bits, 1u128 << address_bits);
+                }
             }
-            if !bits::is_pow2(virtual_pages as u32) {
+            if !bits::is_pow2(virtual_pages) {
                 error!("# of virtual pages is {} but must be a power of 2", virtual_pages);
             }
-            if !bits::is_pow2(virtual_pages as u32) {
+            if !bits::is_pow2(virtual_pages) {
                 error!("Page size is {} but must be a power of 2", page_size);
             }
 
@@ -183,6 +1196,22 @@ impl Config {
                 page_size,
                 idx_size,
                 offset_size,
+                replacement: PageReplacement::from_options(&ext)?,
+                radix: RadixConfig::from_options(&ext, idx_size, max_physical_addr, address_bits)?,
+                huge: HugePageConfig::from_options(&ext, page_size, physical_pages, max_virtual_addr)?,
+                swap_pages: match ext.get("Page Table", "Swap size") {
+                    Some(_) => {
+                        let swap_size = ext.parse::<u64>("Page Table", "Swap size")?;
+                        if swap_size % page_size != 0 {
+                            error!("Swap size is {} bytes but must be a multiple of the page size ({})", swap_size, page_size);
+                        }
+                        Some(swap_size / page_size)
+                    },
+                    None => None,
+                },
+                // Set once the caches are known
+                frame_allocation: FrameAllocation::Sequential,
+                colors: 1,
                 enabled, 
             }
         };
@@ -191,18 +1220,18 @@ impl Config {
             let sets = opts[5].parse::<u32>()?;
             let set_entries = opts[6].parse::<u32>()?;
             let line_size = opts[7].parse::<u32>()?;
-            let idx_size = bits::min_repr(sets as u32) as u32;
-            let offset_size = bits::min_repr(line_size as u32) as u32;
+            let idx_size = bits::min_repr(sets);
+            let offset_size = bits::min_repr(line_size);
 		    let (write_policy, write_miss_policy) = match opts[8].as_str() {
                 "y" => (WritePolicy::WriteThrough, WriteMissPolicy::NoWriteAllocate),
                 "n" => (WritePolicy::WriteBack, WriteMissPolicy::WriteAllocate),
                 s => error!("Field 8 (DC write/write miss policy) must be 'y' or 'n' but was {}", s),
             };
 
-            if sets > MAX_DC_SETS {
+            if strict && sets > MAX_DC_SETS {
                 error!("{} DC sets specified but max is {}", sets, MAX_DC_SETS);
             }
-            if set_entries > MAX_DC_ASSOC {
+            if strict && set_entries > MAX_DC_ASSOC {
                 error!("DC has associativity of {} but max is {}", set_entries, MAX_DC_ASSOC);
             }
             if line_size < 8 {
@@ -221,8 +1250,11 @@ impl Config {
                 line_size,
                 idx_size,
                 offset_size,
-                write_policy,
-                write_miss_policy,
+                write_policy: WritePolicy::from_options(&ext, "Data Cache", write_policy)?,
+                write_miss_policy: WriteMissPolicy::from_options(&ext, "Data Cache", write_miss_policy)?,
+                replacement: Replacement::from_options(&ext, "Data Cache", sets)?,
+                inclusion: Inclusion::Legacy,
+                prefetch: Prefetch::from_options(&ext, "Data Cache")?,
                 enabled: true,
                 id: 1,
             }
@@ -232,8 +1264,8 @@ impl Config {
             let sets = opts[9].parse::<u32>()?;
             let set_entries = opts[10].parse::<u32>()?;
             let line_size = opts[11].parse::<u32>()?;
-            let idx_size = bits::min_repr(sets as u32) as u32;
-            let offset_size = bits::min_repr(line_size as u32) as u32;
+            let idx_size = bits::min_repr(sets);
+            let offset_size = bits::min_repr(line_size);
 		    let (write_policy, write_miss_policy) = match opts[12].as_str() {
                 "y" => (WritePolicy::WriteThrough, WriteMissPolicy::NoWriteAllocate),
                 "n" => (WritePolicy::WriteBack, WriteMissPolicy::WriteAllocate),
@@ -242,7 +1274,7 @@ impl Config {
 
             let enabled = parse_yn!(opts, 15);
 
-            if set_entries > MAX_L2_ASSOC {
+            if strict && set_entries > MAX_L2_ASSOC {
                 error!("L2 cache has associativity of {} but max is {}", set_entries, MAX_L2_ASSOC);
             }
             if line_size < 8 {
@@ -261,73 +1293,493 @@ impl Config {
                 line_size,
                 idx_size,
                 offset_size,
-                write_policy,
-                write_miss_policy,
+                write_policy: WritePolicy::from_options(&ext, "L2 Cache", write_policy)?,
+                write_miss_policy: WriteMissPolicy::from_options(&ext, "L2 Cache", write_miss_policy)?,
+                replacement: Replacement::from_options(&ext, "L2 Cache", sets)?,
+                inclusion: Inclusion::from_options(&ext, "L2 Cache")?,
+                prefetch: Prefetch::from_options(&ext, "L2 Cache")?,
                 enabled,
                 id: 2,
             }
         };
 
 
+        // Any further levels come from "L3 Cache configuration", "L4 Cache configuration", ...
+        let mut caches = vec![dc_config, l2_config];
+        while ext.has_section(&format!("L{} Cache", caches.len() + 1)) {
+            let id = caches.len() + 1;
+            let section = format!("L{} Cache", id);
+            let sets = ext.parse::<u32>(&section, "Number of sets")?;
+            let set_entries = ext.parse::<u32>(&section, "Set size")?;
+            let line_size = ext.parse::<u32>(&section, "Line size")?;
+            let idx_size = bits::min_repr(sets);
+            let offset_size = bits::min_repr(line_size);
+            let (write_policy, write_miss_policy) = match ext.parse::<String>(&section, "Write through/no write allocate")?.as_str() {
+                "y" => (WritePolicy::WriteThrough, WriteMissPolicy::NoWriteAllocate),
+                "n" => (WritePolicy::WriteBack, WriteMissPolicy::WriteAllocate),
+                s => error!("L{} write/write miss policy must be 'y' or 'n' but was {}", id, s),
+            };
+
+            if !bits::is_pow2(sets) {
+                error!("L{} cache has {} sets but must be a power of 2", id, sets);
+            }
+            if strict && set_entries > MAX_L2_ASSOC {
+                error!("L{} cache has associativity of {} but max is {}", id, set_entries, MAX_L2_ASSOC);
+            }
+            if line_size < MIN_L2_LINE_SIZE {
+                error!("L{} line size is {} but minimum is {}", id, line_size, MIN_L2_LINE_SIZE)
+            }
+            if set_entries.count_ones() != 1 {
+                error!("L{} associativity is {} but must be a power of 2", id, set_entries);
+            }
+            if line_size.count_ones() != 1 {
+                error!("L{} line size is {} but must be a power of 2", id, line_size);
+            }
+
+            caches.push(CacheConfig {
+                sets,
+                set_entries,
+                line_size,
+                idx_size,
+                offset_size,
+                write_policy: WritePolicy::from_options(&ext, &section, write_policy)?,
+                write_miss_policy: WriteMissPolicy::from_options(&ext, &section, write_miss_policy)?,
+                replacement: Replacement::from_options(&ext, &section, sets)?,
+                inclusion: Inclusion::from_options(&ext, &section)?,
+                prefetch: Prefetch::from_options(&ext, &section)?,
+                enabled: true,
+                id: id as u8,
+            });
+        }
+
+        // A frame's color is the part of the DC's and L2's set index above the page offset
+        let colors = caches.iter().take(2).filter(|c| c.enabled)
+            .map(|c| (c.sets as u64 * c.line_size as u64 / pt_config.page_size).max(1))
+            .max()
+            .unwrap_or(1);
+        let pt_config = PageTableConfig {
+            frame_allocation: FrameAllocation::from_options(&ext, colors)?,
+            colors,
+            ..pt_config
+        };
+
+        if ext.get("Data Cache", "Inclusion policy").is_some() {
+            error!("The data cache has no level above it, so it can't have an inclusion policy");
+        }
+        // Lines move whole between exclusive levels, so they have to be the same size
+        for (i, cache) in caches.iter().enumerate().filter(|(_, c)| c.enabled && c.inclusion == Inclusion::Exclusive) {
+            if let Some(above) = caches[..i].iter().rev().find(|c| c.enabled) {
+                if above.line_size != cache.line_size {
+                    error!("{} is exclusive of {} so its line size must be {} but was {}",
+                        cache.name(), above.name(), above.line_size, cache.line_size);
+                }
+            }
+        }
+
+        let victim_cache = match ext.parse_or::<u32>("Data Cache", "Victim cache entries", 0)? {
+            0 => None,
+            entries if entries > MAX_VICTIM_ENTRIES => {
+                error!("Victim cache has {} entries but max is {}", entries, MAX_VICTIM_ENTRIES);
+            }
+            entries => Some(CacheConfig {
+                sets: 1,
+                set_entries: entries,
+                idx_size: 0,
+                replacement: Replacement::Lru,
+                inclusion: Inclusion::Exclusive,
+                prefetch: None,
+                id: 0,
+                ..caches[0]
+            }),
+        };
+
         let address_type = match opts[13].as_str() {
             "y" => AddressType::Virtual,
             "n" => AddressType::Physical,
             s => error!("Field 13 (virutal addresses enabled) must be 'y' or 'n' but was {}", s),
         };
             
+        let offline_opt = ext.parse_yn_or("", "Offline OPT", false)?;
+        let report_writebacks = ext.parse_yn_or("", "Report writebacks", false)?;
+        let report_inclusion = ext.get("", "Inclusion policy").is_some();
+
+        let cores = ext.parse_or::<u32>("", "Cores", 1)?;
+        if !(1..=MAX_CORES).contains(&cores) {
+            error!("There are {} cores but must be between 1 and {}", cores, MAX_CORES);
+        }
+        let write_buffer = WriteBufferConfig::from_options(&ext, &caches[0])?;
+        if cores > 1 && write_buffer.is_some() {
+            error!("A write buffer can't be used with more than one core");
+        }
+        if offline_opt && write_buffer.is_some() {
+            error!("Offline OPT can't be used with a write buffer");
+        }
+        if cores > 1 && victim_cache.is_some() {
+            error!("A victim cache can't be used with more than one core");
+        }
+        if cores > 1 && offline_opt {
+            error!("Offline OPT can't be used with more than one core");
+        }
+        if offline_opt && pt_config.enabled && pt_config.virtual_pages > 1u64 << MAX_OPT_VPN_BITS {
+            error!("Offline OPT can't be used with more than 2^{} virtual pages", MAX_OPT_VPN_BITS);
+        }
+        let coherence = CoherenceConfig::from_options(&ext)?;
+        let compare_protocols = ext.parse_yn_or("", "Compare protocols", false)?;
+        if compare_protocols && cores == 1 {
+            error!("Comparing coherence protocols needs more than one core");
+        }
+        let flush_tlb_on_switch = ext.parse_yn_or("", "Flush TLB on switch", false)?;
+        let latency = LatencyConfig::from_options(&ext, caches.len())?;
+        let last_line_size = caches.iter().rev().find(|c| c.enabled).map_or(caches[0].line_size, |c| c.line_size);
+        let physical_bits = u64::BITS - (pt_config.max_physical_addr - 1).leading_zeros();
+        let dram = DramConfig::from_options(&ext, last_line_size, physical_bits)?;
+            
         Ok(Config{
             tlb: tlb_config, 
+            stlb: stlb_config,
             pt: pt_config, 
-            dc: dc_config, 
-            l2: l2_config,
+            caches,
+            victim_cache,
+            write_buffer,
             address_type,
+            address_bits,
+            validation,
+            cores,
+            coherence,
+            compare_protocols,
+            flush_tlb_on_switch,
+            latency,
+            dram,
+            offline_opt,
+            report_writebacks,
+            report_inclusion,
         })
     }
 }
 
+/* === Extended Options === */
+
+/// Option keys which are not part of the 16 positional fields of the reference config format.
+const EXTENDED_KEYS: &[&str] = &[
+    "replacement policy",
+    "random seed",
+    "rrpv bits",
+    "brrip throttle",
+    "leader sets",
+    "psel bits",
+    "offline opt",
+    "aging counter bits",
+    "aging interval",
+    "working set window",
+    "report writebacks",
+    "address bits",
+    "validation",
+    "cores",
+    "coherence protocol",
+    "coherence tracking",
+    "compare protocols",
+    "flush tlb on switch",
+    "latency",
+    "walk latency",
+    "memory latency",
+    "disk latency",
+    "channels",
+    "ranks",
+    "banks",
+    "row size",
+    "address mapping",
+    "row buffer policy",
+    "tcas",
+    "trcd",
+    "trp",
+    "page table levels",
+    "bits per level",
+    "page walk through caches",
+    "walk cache entries",
+    "walk cache associativity",
+    "huge page sizes",
+    "huge page frames",
+    "huge page ranges",
+    "promotion threshold",
+    "swap size",
+    "frame allocation",
+    "write policy",
+    "write miss policy",
+    "colors per process",
+    "inclusion policy",
+    "victim cache entries",
+    "write buffer entries",
+    "write buffer drain",
+    "drain threshold",
+    "prefetcher",
+    "prefetch degree",
+    "prefetch distance",
+];
+
+/// The positional settings of a cache level, which an extra level gives by key instead
+const CACHE_KEYS: &[&str] = &[
+    "number of sets",
+    "set size",
+    "line size",
+    "write through/no write allocate",
+];
+
+/// Optional settings that go beyond the reference config format. Unlike the positional fields,
+/// these are looked up by section header and key, so they can appear anywhere in their section
+/// or be left out entirely.
+#[derive(Default, Debug)]
+struct ExtendedOptions {
+    // (section, key, value)
+    fields: Vec<(String, String, String)>,
+}
+
+impl ExtendedOptions {
+    fn is_extended(key: &str) -> bool {
+        EXTENDED_KEYS.contains(&key.to_lowercase().as_str())
+    }
+
+    /// Whether a key belongs to one of the sections that only exist as extended options: the
+    /// cache settings under "L3 Cache configuration" and beyond, and under "STLB configuration".
+    /// Anything else under those headers (like the trailing "Virtual addresses" field) is still
+    /// positional.
+    fn is_extended_section(section: &str, key: &str) -> bool {
+        let section = section.to_lowercase();
+        let level = section.strip_prefix('l')
+            .and_then(|rest| rest.split(' ').next())
+            .and_then(|n| n.parse::<u32>().ok());
+        let extended = (matches!(level, Some(n) if n >= 3) && section.contains("cache"))
+            || section.starts_with("stlb");
+        extended && CACHE_KEYS.contains(&key.to_lowercase().as_str())
+    }
+
+    fn has_section(&self, section: &str) -> bool {
+        let section = section.to_lowercase();
+        self.fields.iter().any(|(s, _, _)| s.starts_with(&section))
+    }
+
+    fn push(&mut self, section: &str, key: &str, value: String) {
+        self.fields.push((section.to_lowercase(), key.to_lowercase(), value));
+    }
+
+    /// Finds the value of `key` under the section whose header starts with `section`.
+    fn get(&self, section: &str, key: &str) -> Option<&str> {
+        let (section, key) = (section.to_lowercase(), key.to_lowercase());
+        self.fields.iter()
+            .rev()
+            .find(|(s, k, _)| s.starts_with(&section) && *k == key)
+            .map(|(_, _, v)| v.as_str())
+    }
+
+    /// Parses the value of `key`, which must be present.
+    fn parse<T>(&self, section: &str, key: &str) -> Result<T, Box<dyn Error>>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.get(section, key) {
+            Some(v) => match v.parse::<T>() {
+                Ok(t) => Ok(t),
+                Err(e) => error!("{} configuration field '{}' is invalid ({}): {}", section, key, v, e),
+            },
+            None => error!("{} configuration is missing '{}'", section, key),
+        }
+    }
+
+    /// Parses the value of `key`, falling back to `default` if it isn't present.
+    fn parse_or<T>(&self, section: &str, key: &str, default: T) -> Result<T, Box<dyn Error>>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.get(section, key) {
+            Some(_) => self.parse(section, key),
+            None => Ok(default),
+        }
+    }
+
+    /// Parses a 'y' or 'n' value of `key`, falling back to `default` if it isn't present.
+    fn parse_yn_or(&self, section: &str, key: &str, default: bool) -> Result<bool, Box<dyn Error>> {
+        match self.get(section, key) {
+            Some("y") => Ok(true),
+            Some("n") => Ok(false),
+            Some(s) => error!("Field '{}' must be 'y' or 'n' but was {}", key, s),
+            None => Ok(default),
+        }
+    }
+}
+
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 
         writeln!(f, "Data TLB contains {} sets.",    self.tlb.sets)?;
         writeln!(f, "Each set contains {} entries.", self.tlb.set_entries)?;
+        if self.tlb.replacement != Replacement::Lru {
+            writeln!(f, "The TLB uses a {} replacement policy.", self.tlb.replacement.as_str())?;
+        }
+        if let Replacement::Drrip { leader_sets, .. } = self.tlb.replacement {
+            writeln!(f, "Number of DRRIP leader sets per policy is {}.", leader_sets)?;
+        }
         writeln!(f, "Number of bits used for the index is {}.", self.tlb.idx_size)?;
         writeln!(f)?;
 
+        if let Some(stlb) = &self.stlb {
+            writeln!(f, "STLB contains {} sets.", stlb.sets)?;
+            writeln!(f, "Each set contains {} entries.", stlb.set_entries)?;
+            if stlb.replacement != Replacement::Lru {
+                writeln!(f, "The STLB uses a {} replacement policy.", stlb.replacement.as_str())?;
+            }
+            if let Replacement::Drrip { leader_sets, .. } = stlb.replacement {
+                writeln!(f, "Number of DRRIP leader sets per policy is {}.", leader_sets)?;
+            }
+            writeln!(f, "Number of bits used for the index is {}.", stlb.idx_size)?;
+            writeln!(f)?;
+        }
+
         writeln!(f, "Number of virtual pages is {}.", self.pt.virtual_pages)?;
         writeln!(f, "Number of physical pages is {}.", self.pt.physical_pages)?;
         writeln!(f, "Each page contains {} bytes.", self.pt.page_size)?;
         writeln!(f, "Number of bits used for the page table index is {}.", self.pt.idx_size)?;
         writeln!(f, "Number of bits used for the page offset is {}.", self.pt.offset_size)?;
+        match self.pt.replacement {
+            PageReplacement::Lru => (),
+            PageReplacement::Aging { counter_bits, interval } => {
+                writeln!(f, "The page table uses aging replacement with {}-bit counters, aged every {} references.",
+                    counter_bits, interval)?;
+            },
+            PageReplacement::WsClock { window } => {
+                writeln!(f, "The page table uses WSClock replacement with a working set window of {} references.",
+                    window)?;
+            },
+            r => writeln!(f, "The page table uses {} replacement.", r.as_str())?,
+        }
+        if let Some(radix) = &self.pt.radix {
+            let bits = radix.bits[..radix.levels as usize].iter()
+                .map(|b| b.to_string())
+                .collect::<Vec<_>>();
+            writeln!(f, "The page table is a {}-level radix tree using {} index bits per level.",
+                radix.levels, bits.join(", "))?;
+            writeln!(f, "Page table pages start at physical address {:08x}.", radix.base_addr)?;
+            if radix.cached {
+                writeln!(f, "Page table walks read through the caches.")?;
+            }
+            for (level, walk_cache) in radix.walk_caches.iter().enumerate() {
+                if let Some(walk_cache) = walk_cache {
+                    writeln!(f, "Level {} entries are cached in a {}-entry, {}-way page walk cache.",
+                        level + 1, walk_cache.sets * walk_cache.set_entries, walk_cache.set_entries)?;
+                }
+            }
+        }
+        if let Some(huge) = &self.pt.huge {
+            for size in huge.sizes.iter().flatten() {
+                writeln!(f, "{} frames are set aside for {}-byte huge pages.", size.frames, size.size)?;
+            }
+            for range in huge.ranges.iter().flatten() {
+                let size = huge.sizes[range.size].map_or(0, |s| s.size);
+                writeln!(f, "Virtual addresses {:08x}-{:08x} use {}-byte pages.", range.start, range.end, size)?;
+            }
+            if let Some(threshold) = huge.promotion_threshold {
+                writeln!(f, "A region is promoted to a huge page once {}% of its pages are in memory.", threshold)?;
+            }
+        }
+        if let Some(swap_pages) = self.pt.swap_pages {
+            writeln!(f, "Dirty pages are swapped out to {} pages of swap space.", swap_pages)?;
+        }
+        match self.pt.frame_allocation {
+            FrameAllocation::Sequential => (),
+            FrameAllocation::PageColoring { colors_per_process: Some(n) } => {
+                writeln!(f, "Frames are allocated by page coloring with {} colors, {} for each process.",
+                    self.pt.colors, n)?;
+            },
+            FrameAllocation::Random { seed } => writeln!(f, "Frames are allocated at random (seed {}).", seed)?,
+            a => writeln!(f, "Frames are allocated by {} with {} colors.", a.as_str(), self.pt.colors)?,
+        }
         writeln!(f)?;
 
-        writeln!(f, "D-cache contains {} sets.", self.dc.sets)?;
-        writeln!(f, "Each set contains {} entries.", self.dc.set_entries)?;
-        writeln!(f, "Each line is {} bytes.", self.dc.line_size)?;
-        writeln!(f, "The cache uses a {}write-allocate and write-{} policy.", 
-                if self.dc.write_miss_policy == WriteMissPolicy::WriteAllocate { "" } else { "no " },
-                if self.dc.write_policy == WritePolicy::WriteBack { "back" } else { "through" })?;
-        writeln!(f, "Number of bits used for the index is {}.", self.dc.idx_size)?;
-        writeln!(f, "Number of bits used for the offset is {}.", self.dc.offset_size)?;
-        writeln!(f)?;
-
-        writeln!(f, "L2-cache contains {} sets.", self.l2.sets)?;
-        writeln!(f, "Each set contains {} entries.", self.l2.set_entries)?;
-        writeln!(f, "Each line is {} bytes.", self.l2.line_size)?;
-        writeln!(f, "The cache uses a {}write-allocate and write-{} policy.", 
-                if self.l2.write_miss_policy == WriteMissPolicy::WriteAllocate { "" } else { "no " },
-                if self.l2.write_policy == WritePolicy::WriteBack { "back" } else { "through" })?;
-        writeln!(f, "Number of bits used for the index is {}.", self.l2.idx_size)?;
-        writeln!(f, "Number of bits used for the offset is {}.", self.l2.offset_size)?;
-        writeln!(f)?;
+        for cache in self.caches.iter() {
+            let name = if cache.id == 1 { "D".to_string() } else { cache.name() };
+            writeln!(f, "{}-cache contains {} sets.", name, cache.sets)?;
+            writeln!(f, "Each set contains {} entries.", cache.set_entries)?;
+            writeln!(f, "Each line is {} bytes.", cache.line_size)?;
+            writeln!(f, "The cache uses a {}write-allocate and write-{} policy.", 
+                    if cache.write_miss_policy == WriteMissPolicy::WriteAllocate { "" } else { "no " },
+                    if cache.write_policy == WritePolicy::WriteBack { "back" } else { "through" })?;
+            if cache.replacement != Replacement::Lru {
+                writeln!(f, "The cache uses a {} replacement policy.", cache.replacement.as_str())?;
+            }
+            if let Replacement::Drrip { leader_sets, .. } = cache.replacement {
+                writeln!(f, "Number of DRRIP leader sets per policy is {}.", leader_sets)?;
+            }
+            if let Some(prefetch) = cache.prefetch {
+                writeln!(f, "The cache uses {} prefetching of degree {} and distance {}.",
+                    prefetch.as_str(), prefetch.degree(), prefetch.distance())?;
+            }
+            if matches!(cache.inclusion, Inclusion::Exclusive | Inclusion::Nine) {
+                writeln!(f, "The cache is {} of the level above.", cache.inclusion.as_str())?;
+            }
+            writeln!(f, "Number of bits used for the index is {}.", cache.idx_size)?;
+            writeln!(f, "Number of bits used for the offset is {}.", cache.offset_size)?;
+            if let (1, Some(victim_cache)) = (cache.id, &self.victim_cache) {
+                writeln!(f, "Evicted lines go to a {}-entry fully associative victim cache.", victim_cache.set_entries)?;
+            }
+            if let (1, Some(write_buffer)) = (cache.id, &self.write_buffer) {
+                match write_buffer.drain {
+                    DrainPolicy::Threshold(threshold) => writeln!(f,
+                        "Writes on their way below wait in a {}-entry write buffer, drained while {} or more are waiting.",
+                        write_buffer.entries, threshold)?,
+                    drain => writeln!(f, "Writes on their way below wait in a {}-entry write buffer, drained {}.",
+                        write_buffer.entries, if drain == DrainPolicy::Eager { "whenever the level below is idle" } else { "when full" })?,
+                }
+            }
+            writeln!(f)?;
+        }
 
         writeln!(f, "The addresses read in are {} addresses.", self.address_type.as_str().to_lowercase())?;
+        if self.address_bits != 32 {
+            writeln!(f, "Addresses are {} bits wide.", self.address_bits)?;
+        }
+        if self.cores > 1 {
+            writeln!(f, "There are {} cores, each with a private data TLB and data cache kept coherent with {}{}.",
+                self.cores, self.coherence.protocol.as_str(), if self.coherence.directory { " through a directory" } else { "" })?;
+            if self.compare_protocols {
+                writeln!(f, "The trace will be replayed with every coherence protocol for comparison.")?;
+            }
+        }
+        if let Some(latency) = &self.latency {
+            let stlb = self.stlb.map_or(String::new(), |_| format!(", STLB {}", latency.stlb));
+            let caches: String = self.caches.iter().zip(latency.caches.iter())
+                .map(|(cache, cycles)| format!(", {} {}", cache.name(), cycles))
+                .collect();
+            let memory = match self.dram {
+                Some(_) => "as DRAM takes".to_string(),
+                None => latency.memory.to_string(),
+            };
+            writeln!(f, "Accesses are timed in cycles: TLB {}{}, page walk {}{}, memory {}, disk {}.",
+                latency.tlb, stlb, latency.walk, caches, memory, latency.disk)?;
+        }
+        if let Some(dram) = &self.dram {
+            writeln!(f, "Main memory is DRAM with {} channel(s) of {} rank(s) of {} banks, with {}-byte rows.",
+                dram.channels, dram.ranks, dram.banks, dram.row_size)?;
+            writeln!(f, "Addresses are mapped {} and rows are left {}. tCAS, tRCD and tRP are {}, {} and {} cycles.",
+                dram.mapping.as_str(), dram.policy.as_str(),
+                dram.t_cas, dram.t_rcd, dram.t_rp)?;
+        }
+        if self.flush_tlb_on_switch {
+            writeln!(f, "Context switches flush the TLBs instead of relying on ASIDs.")?;
+        }
+        if self.validation == Validation::Relaxed {
+            writeln!(f, "Geometry is checked with {} validation.", self.validation.as_str())?;
+        }
 
         if !self.tlb.enabled {
             writeln!(f, "TLB is disabled in this configuration.")?;
         }
 
-        if !self.l2.enabled {
-            writeln!(f, "L2 cache is disabled in this configuration.")?;
+        for cache in self.caches.iter().filter(|c| !c.enabled) {
+            writeln!(f, "{} cache is disabled in this configuration.", cache.name())?;
+        }
+
+        if self.offline_opt {
+            writeln!(f, "The trace will be replayed with Belady's OPT replacement for comparison.")?;
         }
         Ok(())
     }
@@ -1,40 +1,159 @@
 /* Austin Rhodes
  * PA 1: Memory Hierarchy Simulation
  * COSC 530 -- Fall 2022 */
-use memsim_rs::config::Config;
-use memsim_rs::memory::Memory;
+use memsim_rs::config::{Config, CoherenceConfig};
+use memsim_rs::memory::{Memory, MemoryResponse, Oracle, OptComparison, ProtocolComparison};
 use std::io::BufRead;
 use std::env;
 
 const TABLE_HEADER: &str =
-     /*Type*/"Virt.  Page TLB    TLB TLB  PT   Phys        DC  DC          L2  L2  \n\
-     Address  Page # Off  Tag    Ind Res. Res. Pg # DC Tag Ind Res. L2 Tag Ind Res.\n\
-     -------- ------ ---- ------ --- ---- ---- ---- ------ --- ---- ------ --- ----";
+     /*Type*/"Virt.  Page TLB    TLB TLB \n\
+     Address  Page # Off  Tag    Ind Res.\n\
+     -------- ------ ---- ------ --- ----";
 
-/// Read the trace file in from stdin. Produces an iterator of tuples of `char` and `u32`,
-/// which can be thought of as ('r' | 'w', addr) 
+const STLB_HEADER: [&str; 3] = [
+    " STLB   STL STLB",
+    " Tag    Ind Res.",
+    " ------ --- ----",
+];
+
+const PAGE_HEADER: [&str; 3] = [
+    " PT   Phys",
+    " Res. Pg #",
+    " ---- ----",
+];
+
+const PAGE_SIZE_HEADER: [&str; 3] = [
+    " Page ",
+    " Size ",
+    " -----",
+];
+
+const CORE_HEADER: [&str; 3] = [
+    " Core Line ",
+    " Id   State",
+    " ---- -----",
+];
+
+const CYCLES_HEADER: [&str; 3] = [
+    " Access  ",
+    " Cycles  ",
+    " --------",
+];
+
+/// Builds the column header of the access table: the translation columns (with the STLB's, if
+/// there is one), a group of columns for each cache level, and any optional columns.
+fn table_header(config: &Config) -> String {
+    // The address column widens to fit addresses wider than 32 bits
+    let extra = (((config.address_bits + 3) / 4) as usize).saturating_sub(8);
+    TABLE_HEADER.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let mut line = match i {
+                0 => format!("{}{}", " ".repeat(extra), line),
+                1 => line.replacen("Address ", &format!("Address {}", " ".repeat(extra)), 1),
+                _ => line.replacen("--------", &"-".repeat(8 + extra), 1),
+            };
+            if config.stlb.is_some() {
+                line.push_str(STLB_HEADER[i]);
+            }
+            line.push_str(PAGE_HEADER[i]);
+            if config.pt.huge.is_some() {
+                line.push_str(PAGE_SIZE_HEADER[i]);
+            }
+            for cache in config.caches.iter() {
+                let name = cache.name();
+                line.push_str(&match i {
+                    0 => format!("        {:<4}{:<4}", name, name),
+                    1 => format!(" {} Tag Ind Res.", name),
+                    _ => " ------ --- ----".to_string(),
+                });
+                if cache.id == 1 && config.victim_cache.is_some() {
+                    line.push_str([" VC  ", " Res.", " ----"][i]);
+                }
+            }
+            if config.report_writebacks {
+                for cache in config.caches.iter() {
+                    line.push_str(&match i {
+                        0 => format!(" {:<4}", cache.name()),
+                        1 => " WBs ".to_string(),
+                        _ => " ----".to_string(),
+                    });
+                }
+                line.push_str(match i {
+                    0 => " Disk",
+                    1 => " Wrs.",
+                    _ => " ----",
+                });
+            }
+            if config.cores > 1 {
+                line.push_str(CORE_HEADER[i]);
+            }
+            if config.latency.is_some() {
+                line.push_str(CYCLES_HEADER[i]);
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// One line of the trace: (core, 'r' | 'w', addr, pc), or (core, 'c', pid, None) for a context
+/// switch
+type TraceRef = (usize, char, u64, Option<u64>);
+
+/// Read the trace file in from stdin. Produces an iterator of tuples of `usize`, `char`, `u64`
+/// and `Option<u64>`, which can be thought of as (core, 'r' | 'w', addr, pc). The PC is an
+/// optional third field in hex like the address (`r:addr:pc`), and the core an optional
+/// leading field in decimal (`1:r:addr`, core 0 if it's left out). A context switch names the
+/// process the core runs from then on, in decimal (`c:pid`).
 pub fn trace_from_stdin(
-    stdin_lock: std::io::StdinLock
-) -> Result<impl Iterator<Item = (char, u32)> + '_, Box<dyn std::error::Error>> {
+    stdin_lock: std::io::StdinLock<'_>
+) -> Result<impl Iterator<Item = TraceRef> + '_, Box<dyn std::error::Error>> {
+    // A line that can't be read is skipped, like a malformed one, rather than ending the trace
+    #[allow(clippy::lines_filter_map_ok)]
     let lines = stdin_lock.lines()
         .filter_map(|line| line.ok());
 
-    let trace_refs = lines.filter_map(|line| -> Option<(char, u32)> {
-        let (access_type_str, access_addr_str) = line.split_at(2);
+    let trace_refs = lines.filter_map(|line| -> Option<TraceRef> {
+        let (core, line) = match line.split_once(':') {
+            Some((core, rest)) if !core.is_empty() && core.chars().all(|c| c.is_ascii_digit()) => {
+                (core.parse::<usize>().ok()?, rest)
+            },
+            _ => (0, line.as_str()),
+        };
+        // Lines too short to hold an access type and its ':' are skipped like any bad line
+        let (access_type_str, rest) = (line.get(..2)?, line.get(2..)?);
+        let mut fields = rest.split(|c: char| c == ':' || c.is_whitespace()).filter(|f| !f.is_empty());
 
         let access_type = access_type_str.chars().next().ok_or("bad trace char");
-        let access_addr = u32::from_str_radix(access_addr_str, 16);
+        if let Ok(switch @ ('c' | 'C')) = access_type {
+            let pid = fields.next()?.parse::<u32>().ok()?;
+            return Some((core, switch, pid as u64, None));
+        }
+        let access_addr = u64::from_str_radix(fields.next().unwrap_or(""), 16);
+        let pc = fields.next().map(|pc| u64::from_str_radix(pc, 16));
 
-        if access_type.is_ok() && access_addr.is_ok() {
-            Some((access_type.unwrap(), access_addr.unwrap()))
-        } else {
-            None
+        match (access_type, access_addr, pc) {
+            (Ok(access_type), Ok(access_addr), None) => Some((core, access_type, access_addr, None)),
+            (Ok(access_type), Ok(access_addr), Some(Ok(pc))) => Some((core, access_type, access_addr, Some(pc))),
+            _ => None,
         }
     });
 
     Ok(trace_refs)
 }
 
+/// Issues one line of the trace to a memory system: an access, or a context switch (which has
+/// no response).
+fn issue(mem: &mut Memory, &(core, trace_char, trace_addr, pc): &TraceRef)
+    -> Result<Option<MemoryResponse>, Box<dyn std::error::Error>> {
+    match trace_char {
+        'c' | 'C' => mem.context_switch(core, trace_addr as u32).map(|_| None),
+        _ => mem.access(core, trace_char, trace_addr, pc).map(Some),
+    }
+}
+
 fn main() {
     let config_path = match env::var("MEMSIM_CONFIG") {
         Ok(cfg) => cfg,
@@ -54,7 +173,7 @@ fn main() {
 
     let addr_type = config.address_type;
 
-    let mut mem = Memory::new(config);
+    let mut mem = Memory::new(config.clone());
 
     // Oddity: Returning 'static handles was not added until rustc 
     // version 1.61.0. For now, we must instantiate the stdin lock 
@@ -65,12 +184,26 @@ fn main() {
     let trace_reader = trace_from_stdin(stdin_lock)
         .expect("Error reading from stdin");
 
-    println!("{} {}", addr_type.as_str(), TABLE_HEADER);
-    for (trace_char, trace_addr) in trace_reader {
-        let access_result = mem.access(trace_char, trace_addr);
-        match access_result {
-            Ok(access) => {
+    // The whole trace is buffered so it can be replayed for the offline OPT comparison
+    let trace: Vec<TraceRef> = trace_reader.collect();
+    let mut refs = Vec::<(Option<u64>, u64)>::new();
+
+    println!("{} {}", addr_type.as_str(), table_header(&config));
+    for trace_ref in trace.iter() {
+        match issue(&mut mem, trace_ref) {
+            Ok(Some(access)) => {
                 println!("{}", access);
+                if config.offline_opt {
+                    refs.push((access.translation_key(), access.physical_addr()));
+                }
+            }
+            Ok(None) => {
+                let &(core, _, pid, _) = trace_ref;
+                if config.cores > 1 {
+                    println!("Context switch to process {} on core {}", pid, core);
+                } else {
+                    println!("Context switch to process {}", pid);
+                }
             }
             Err(e) => {
                 eprintln!("Invalid access: {}", e);
@@ -78,4 +211,30 @@ fn main() {
             }
         }
     }
+
+    println!("{}", mem.stats());
+
+    if config.compare_protocols {
+        let runs = CoherenceConfig::all()
+            .map(|coherence| {
+                let mut protocol_mem = Memory::new(Config { coherence, ..config.clone() });
+                for trace_ref in trace.iter() {
+                    let _ = issue(&mut protocol_mem, trace_ref);
+                }
+                (coherence.name(), protocol_mem.stats())
+            })
+            .collect();
+        println!("{}", ProtocolComparison { runs });
+    }
+
+    if config.offline_opt {
+        let oracle = Oracle::new(&config, refs.into_iter());
+        let mut opt_mem = Memory::with_oracle(config, oracle);
+        for trace_ref in trace.iter() {
+            // Any invalid access would have already ended the first run
+            let _ = issue(&mut opt_mem, trace_ref);
+        }
+        let comparison = OptComparison { actual: mem.stats(), opt: opt_mem.stats() };
+        println!("{}", comparison);
+    }
 }
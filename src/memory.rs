@@ -1,37 +1,85 @@
 mod page;
 mod tlb;
 mod cache;
+mod replacement;
+mod oracle;
+mod prefetch;
+mod coherence;
+mod dram;
+mod write_buffer;
+mod stats;
+
+use std::collections::HashSet;
+
+pub use stats::{SimulationStats, QueryCounter, DuelStats, OptComparison, Writebacks, InclusionStats, PrefetchStats, WalkStats, HugePageStats, CoherenceStats, CoherenceCounts, CoherenceTraffic, CoreStats, ProtocolComparison, ProcessStats, ProcessCounts, Latency, DramStats, BankStats, SwapStats, WriteBufferStats};
+pub use oracle::Oracle;
+pub use coherence::LineState;
 
 use crate::{
-    config::{self, Config, WritePolicy::*},
+    config::{self, Config, DrainPolicy, Inclusion, WriteMissPolicy::*, WritePolicy::*},
     memory::{
         page::{PageTable, PageTableResponse},
-        cache::CPUCache, 
+        cache::{CPUCache, CacheEntry, CacheResponse},
         tlb::{TLB,TLBResponse},
+        replacement::Opt,
+        coherence::Directory,
+        dram::{Dram, RowResult},
+        write_buffer::WriteBuffer,
     }, utils::bits
 };
 
+/// The levels private to a core that isn't running: its TLBs and its data cache.
+#[derive(Debug)]
+struct PrivateLevels {
+    tlb: TLB,
+    stlb: Option<TLB>,
+    dc: CPUCache,
+}
+
+/// What snooping decided about an access, to be applied once the access is done.
+struct Snoop {
+    /// The state this core's copy takes, if the access changes it
+    fill: Option<LineState>,
+    /// Another core's DC hands the line over, so a miss doesn't have to look below the DCs
+    from_peer: bool,
+}
+
+/// What happened at each level of cache during one access.
+struct CacheAccess {
+    responses: Vec<Option<CacheResponse>>,
+    /// Result of probing the victim cache, if it was probed
+    victim: Option<QueryResult>,
+    /// The access went past the last enabled level to main memory
+    memory: bool,
+    /// The access was a write that had to wait for the full write buffer to drain a line
+    stalled: bool,
+}
+
 struct TranslationResponse {
-    vpn: Option<u32>,
-    ppn: u32,
-    page_offset: u32,
+    vpn: Option<u64>,
+    ppn: u64,
+    page_offset: u64,
+    /// log2 of the base pages in the page that translated the address
+    page_shift: u32,
     pt_response: Option<PageTableResponse>,
     tlb_response: Option<TLBResponse>,
+    stlb_response: Option<TLBResponse>,
 }
 
 /// Represents the input access events.
 /// 
 /// Contains either a physical or virtual address (based on config) and can
 /// either be a `Read` or a `Write`.
+#[derive(Copy, Clone)]
 pub enum AccessEvent {
-    Read(u32),
-    Write(u32),
+    Read(u64),
+    Write(u64),
 }
 
 impl AccessEvent {
     fn from_raw(
         access_type: char, 
-        addr: u32, 
+        addr: u64, 
     ) -> Result<AccessEvent, Box<dyn std::error::Error>> {
         let access_event = match access_type {
             'r' | 'R' => AccessEvent::Read(addr),
@@ -41,7 +89,7 @@ impl AccessEvent {
         Ok(access_event)
     }
 
-    fn addr(&self) -> u32 {
+    fn addr(&self) -> u64 {
         match self {
             AccessEvent::Write(addr) => *addr,
             AccessEvent::Read(addr) => *addr,
@@ -61,30 +109,902 @@ impl AccessEvent {
 pub struct Memory {
     #[allow(dead_code)]
     tlb: TLB,
+    stlb: Option<TLB>,
     pt: PageTable,
-    dc: CPUCache,
-    l2: CPUCache,
+    /// Page walk cache for each upper level of a radix page table, root first
+    walk_caches: Vec<Option<TLB>>,
+    /// Every configured cache level, closest to the CPU first (disabled levels are never touched)
+    caches: Vec<CPUCache>,
+    victim: Option<CPUCache>,
+    /// Writes the DC has sent on below, waiting to be written to the next level
+    write_buffer: Option<WriteBuffer>,
+    /// The core whose private levels are in `tlb`, `stlb` and `caches[0]`
+    core: usize,
+    /// Every other core's private levels, swapped in when that core makes an access (so the
+    /// rest of the hierarchy only ever sees one of each)
+    parked: Vec<Option<PrivateLevels>>,
+    /// The process running on each core, whose ID is the ASID its translations are tagged with
+    pids: Vec<u32>,
+    /// Lines (by block) each core lost to another core's write, for telling coherence misses
+    /// apart from the rest
+    lost: Vec<HashSet<u64>>,
+    /// Tracks the cores' copies in place of snooping, if coherence goes through a directory
+    directory: Option<Directory>,
+    /// Main memory's banks, if it is modeled as DRAM
+    dram: Option<Dram>,
+    /// Cycles DRAM has spent on the access being simulated
+    dram_cycles: u64,
     config: Config,
+    stats: SimulationStats,
+    oracle: Option<Oracle>,
 }
 
 impl Memory {
     /// Configures all submodules of the memory system and initializes the memory simulation object.
     pub fn new(config: Config) -> Self {
-        let tlb = TLB::new(config.tlb.clone());
-        let pt = PageTable::new(config.pt.clone());
-        let dc = CPUCache::new(config.dc.clone(), config.clone());
-        let l2 = CPUCache::new(config.l2.clone(), config.clone());
-        Memory {tlb, pt, dc, l2, config}
+        Memory::build(config, None)
+    }
+
+    /// Initializes a memory system whose TLB and caches replace with Belady's OPT, using the
+    /// future knowledge in the oracle. The trace must be replayed in the same order the oracle
+    /// was built from.
+    pub fn with_oracle(config: Config, oracle: Oracle) -> Self {
+        Memory::build(config, Some(oracle))
+    }
+
+    /// Builds the memory system, with OPT in place of the configured replacement policies if
+    /// there is an oracle.
+    fn build(config: Config, oracle: Option<Oracle>) -> Self {
+        // Both TLB levels are keyed by vpn and ASID, so they can share their future
+        let new_tlb = |tlb: config::TLBConfig| match oracle.as_ref() {
+            Some(oracle) => TLB::with_policy(tlb, Box::new(Opt::new(
+                tlb.sets as usize, tlb.set_entries as usize, oracle.tlb.clone(), oracle.now.clone()))),
+            None => TLB::new(tlb),
+        };
+        let new_cache = |level: usize| match oracle.as_ref() {
+            Some(oracle) => {
+                let cache = config.caches[level];
+                CPUCache::with_policy(cache, config.pt, Box::new(Opt::new(
+                    cache.sets as usize, cache.set_entries as usize, oracle.caches[level].clone(), oracle.now.clone())))
+            },
+            None => CPUCache::new(config.caches[level], config.pt),
+        };
+        let tlb = new_tlb(config.tlb);
+        let stlb = config.stlb.map(new_tlb);
+        let pt = PageTable::new(config.pt);
+        let walk_caches = Memory::walk_caches(&config);
+        let caches = (0..config.caches.len()).map(new_cache).collect();
+        let victim = config.victim_cache.map(|vc| CPUCache::new(vc, config.pt));
+        let parked = (0..config.cores)
+            .map(|core| (core > 0).then(|| PrivateLevels {
+                tlb: new_tlb(config.tlb),
+                stlb: config.stlb.map(new_tlb),
+                dc: new_cache(0),
+            }))
+            .collect();
+        let stats = Memory::initial_stats(&config, &walk_caches);
+        Memory {
+            tlb, stlb, pt, walk_caches, caches, victim,
+            write_buffer: config.write_buffer.map(|wb| WriteBuffer::new(wb, config.caches[0].offset_size)),
+            core: 0,
+            parked,
+            pids: vec![0; config.cores as usize],
+            lost: vec![HashSet::new(); config.cores as usize],
+            directory: (config.cores > 1 && config.coherence.directory).then(Directory::default),
+            dram: config.dram.map(Dram::new),
+            dram_cycles: 0,
+            config, stats, oracle,
+        }
+    }
+
+    /// Statistics before any access, with a counter for each optional part of the hierarchy.
+    fn initial_stats(config: &Config, walk_caches: &[Option<TLB>]) -> SimulationStats {
+        SimulationStats {
+            stlb: config.stlb.map(|_| QueryCounter::default()),
+            victim: config.victim_cache.map(|_| QueryCounter::default()),
+            walk: config.pt.radix.map(|_| WalkStats {
+                caches: walk_caches.iter().map(|c| c.as_ref().map(|_| QueryCounter::default())).collect(),
+                ..WalkStats::default()
+            }),
+            huge_pages: config.pt.huge.map(|huge| HugePageStats::new(config.pt.page_size, &huge)),
+            coherence: Some(CoherenceStats::new(config.cores as usize, config.coherence.directory))
+                .filter(|_| config.cores > 1),
+            latency: config.latency.as_ref().map(|_| Latency::new(config.caches.len())),
+            dram: config.dram.map(|dram| DramStats::new(dram.channels, dram.ranks, dram.banks)),
+            swap: config.pt.swap_pages.map(|capacity| SwapStats { capacity, ..SwapStats::default() }),
+            write_buffer: config.write_buffer.map(|_| WriteBufferStats::default()),
+            ..SimulationStats::new(config.caches.len())
+        }
+    }
+
+    /// Builds the page walk caches of a radix page table (there are none for a flat one).
+    fn walk_caches(config: &Config) -> Vec<Option<TLB>> {
+        config.pt.radix.map_or_else(Vec::new, |radix| {
+            radix.walk_caches[..radix.levels as usize - 1].iter()
+                .map(|walk_cache| walk_cache.map(TLB::new))
+                .collect()
+        })
+    }
+
+    /// Statistics accumulated over every access issued so far. The DC's are summed over the
+    /// cores.
+    pub fn stats(&self) -> SimulationStats {
+        let parked_dcs = || self.parked.iter().flatten().map(|p| &p.dc);
+        SimulationStats {
+            duels: self.caches.iter()
+                .enumerate()
+                .map(|(level, cache)| {
+                    let mut duel = cache.duel_stats().filter(|_| cache.config().enabled)?;
+                    if level == 0 {
+                        parked_dcs().filter_map(|dc| dc.duel_stats()).for_each(|d| duel += &d);
+                    }
+                    Some(duel)
+                })
+                .collect(),
+            writebacks: self.stats.writebacks.clone().filter(|_| self.config.report_writebacks),
+            inclusion: self.stats.inclusion.clone().filter(|_| self.config.report_inclusion),
+            processes: self.stats.processes.clone().filter(|p| p.switches > 0),
+            swap: self.stats.swap.map(|swap| SwapStats { used: self.pt.swap_used().unwrap_or(0), ..swap }),
+            // Lines still waiting will be written to the next level too
+            write_buffer: self.stats.write_buffer.map(|buffer| WriteBufferStats {
+                drained: buffer.drained + self.write_buffer.as_ref().map_or(0, |wb| wb.len() as u64),
+                ..buffer
+            }),
+            prefetches: self.caches.iter()
+                .enumerate()
+                .map(|(level, cache)| {
+                    let mut prefetch = cache.prefetch_stats().filter(|_| cache.config().enabled)?;
+                    if level == 0 {
+                        parked_dcs().filter_map(|dc| dc.prefetch_stats()).for_each(|p| prefetch += &p);
+                    }
+                    Some(prefetch)
+                })
+                .collect(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Swaps the private levels of `core` in for those of the core that ran last.
+    fn switch_to(&mut self, core: usize) {
+        if core == self.core {
+            return;
+        }
+        let parked = self.parked[core].take().expect("every core but the running one is parked");
+        let running = PrivateLevels {
+            tlb: std::mem::replace(&mut self.tlb, parked.tlb),
+            stlb: std::mem::replace(&mut self.stlb, parked.stlb),
+            dc: std::mem::replace(&mut self.caches[0], parked.dc),
+        };
+        self.parked[self.core] = Some(running);
+        self.core = core;
+    }
+
+    /// Has the TLBs, walk caches and page table translate in the address space of process `pid`.
+    fn enter_process(&mut self, pid: u32) {
+        self.tlb.set_asid(pid);
+        if let Some(stlb) = self.stlb.as_mut() {
+            stlb.set_asid(pid);
+        }
+        for walk_cache in self.walk_caches.iter_mut().flatten() {
+            walk_cache.set_asid(pid);
+        }
+        self.pt.switch_to(pid);
+    }
+
+    /// Switches `core` to running process `pid`. The old process's translations stay in the
+    /// core's TLBs under its ASID, unless switches flush them (along with the walk caches, which
+    /// every core shares).
+    pub fn context_switch(&mut self, core: usize, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+        if core >= self.parked.len() {
+            error!("core {} doesn't exist (there are {} cores)", core, self.parked.len());
+        }
+        if pid > config::MAX_PID {
+            error!("process ID {} is too large (maximum is {})", pid, config::MAX_PID);
+        }
+        self.switch_to(core);
+
+        let processes = self.stats.processes.get_or_insert_with(ProcessStats::default);
+        processes.switches += 1;
+        processes.processes.entry(pid).or_default();
+        if self.config.flush_tlb_on_switch {
+            processes.flushes += 1;
+            self.tlb.flush();
+            if let Some(stlb) = self.stlb.as_mut() {
+                stlb.flush();
+            }
+            for walk_cache in self.walk_caches.iter_mut().flatten() {
+                walk_cache.flush();
+            }
+        }
+        self.pids[core] = pid;
+        Ok(())
+    }
+
+    /// Drops the translations of an evicted page from every core's TLBs.
+    fn clean_tlbs(&mut self, ppn: u64) {
+        let parked = self.parked.iter_mut().flatten().map(|p| (&mut p.tlb, &mut p.stlb));
+        for (tlb, stlb) in std::iter::once((&mut self.tlb, &mut self.stlb)).chain(parked) {
+            tlb.clean_ppn(ppn);
+            if let Some(stlb) = stlb.as_mut() {
+                stlb.clean_ppn(ppn);
+            }
+        }
+    }
+
+    /// Keeps the other cores' DCs coherent ahead of an access by the running core. A read miss
+    /// has the other copies shared (with their owner handing the line over or writing it back,
+    /// depending on the protocol); a write to a line this core doesn't own invalidates every
+    /// other copy. Requests are broadcast to every other core, or sent to the directory.
+    fn snoop(&mut self, access_event: AccessEvent, writebacks: &mut Writebacks) -> Snoop {
+        let unchanged = Snoop { fill: None, from_peer: false };
+        let stats = match self.stats.coherence.as_mut() {
+            Some(stats) => stats,
+            None => return unchanged,
+        };
+        let protocol = self.config.coherence.protocol;
+        let addr = access_event.addr();
+        let line_size = self.config.caches[0].line_size as u64;
+        let line = addr & !(line_size - 1);
+        let block = addr >> self.config.caches[0].offset_size;
+        let state = self.caches[0].line_state(addr);
+        let write = access_event.is_write();
+
+        if state == LineState::Invalid && self.lost[self.core].remove(&block) {
+            stats.count(self.core, line, |c| c.coherence_misses += 1);
+        }
+
+        let upgrade = match (write, state) {
+            (_, LineState::Invalid) => false,
+            (true, LineState::Shared | LineState::Owned | LineState::Forward) => true,
+            _ => return unchanged,
+        };
+        if upgrade {
+            stats.count(self.core, line, |c| c.upgrades += 1);
+        }
+
+        // Which other cores had a copy, and which of them answered for the line
+        let mut holders = 0u32;
+        let mut owner = None;
+        let mut dirty_copies = 0;
+        let mut from_peer = false;
+        for (core, parked) in self.parked.iter_mut().enumerate() {
+            let dc = match parked.as_mut() {
+                Some(parked) => &mut parked.dc,
+                None => continue,
+            };
+            let peer_state = dc.line_state(addr);
+            if peer_state == LineState::Invalid {
+                continue;
+            }
+            holders |= 1 << core;
+            if peer_state.is_owner() {
+                owner = Some(core);
+            }
+
+            let action = if write {
+                coherence::remote_write(protocol, peer_state, upgrade)
+            } else {
+                coherence::remote_read(protocol, peer_state)
+            };
+            if action.supplies || action.writes_back {
+                stats.count(core, line, |c| c.interventions += 1);
+            }
+            if action.writes_back {
+                dirty_copies += 1;
+            }
+            if action.next == LineState::Invalid {
+                stats.count(core, line, |c| c.invalidations += 1);
+                self.lost[core].insert(block);
+            }
+            from_peer |= action.supplies;
+            dc.set_state(addr, action.next);
+        }
+
+        let traffic = &mut stats.traffic;
+        traffic.requests += 1;
+        match self.directory.as_ref() {
+            // Every other core looks the line up, and those with a copy answer
+            None => {
+                let others = self.parked.len() as u64 - 1;
+                traffic.lookups += others;
+                traffic.messages += others + holders.count_ones() as u64;
+            },
+            // The request goes to the directory, which forwards it to the owner for a read, or
+            // has every sharer it knows of invalidated for a write. Each of those answers.
+            Some(directory) => {
+                let entry = directory.lookup(block);
+                let contacted = if write {
+                    ((entry.sharers | holders) & !(1 << self.core)).count_ones() as u64
+                } else {
+                    entry.owner.filter(|&core| core != self.core).or(owner).is_some() as u64
+                };
+                traffic.lookups += 1;
+                traffic.messages += 1 + 2 * contacted;
+            },
+        }
+        if from_peer {
+            traffic.transfers += 1;
+        }
+
+        // A dirty copy goes below the DCs, where this core's miss will find it
+        for _ in 0..dirty_copies {
+            writebacks.caches[0] += 1;
+            self.write_back(0, line, writebacks);
+        }
+
+        let fill = if write { LineState::Exclusive } else { coherence::read_fill(protocol, holders != 0) };
+        Snoop { fill: Some(fill), from_peer }
+    }
+
+    /// Puts the running core's copy of a line into the state snooping decided on, once the access
+    /// is done with it. A write leaves the only copy (dirty, unless the DC writes through).
+    fn settle(&mut self, addr: u64, fill: LineState, write: bool, writebacks: &mut Writebacks) {
+        let dirty = matches!(self.caches[0].line_state(addr), LineState::Modified | LineState::Owned);
+        let state = match fill {
+            _ if dirty && (write || fill == LineState::Exclusive) => LineState::Modified,
+            // A dirty line handed up by an exclusive level can only stay dirty while shared in MOESI
+            _ if dirty && self.config.coherence.protocol == config::CoherenceProtocol::Moesi => LineState::Owned,
+            _ if dirty => {
+                writebacks.caches[0] += 1;
+                self.write_back(0, addr, writebacks);
+                fill
+            },
+            _ if write => LineState::Exclusive,
+            _ => fill,
+        };
+        self.caches[0].set_state(addr, state);
+
+        if let Some(directory) = self.directory.as_mut() {
+            let running = std::iter::once((self.core, &self.caches[0]));
+            let parked = self.parked.iter()
+                .enumerate()
+                .filter_map(|(core, parked)| parked.as_ref().map(|parked| (core, &parked.dc)));
+            let mut holders = 0;
+            let mut owner = None;
+            for (core, dc) in running.chain(parked) {
+                let state = dc.line_state(addr);
+                if state != LineState::Invalid {
+                    holders |= 1 << core;
+                }
+                if state.is_owner() {
+                    owner = Some(core);
+                }
+            }
+            directory.update(addr >> self.config.caches[0].offset_size, holders, owner, write);
+        }
+    }
+
+    /// Levels of cache that are actually in use, closest to the CPU first
+    fn enabled_levels(&self) -> Vec<usize> {
+        (0..self.caches.len()).filter(|&i| self.config.caches[i].enabled).collect()
+    }
+
+    /// The next level in use below `level`, or `None` if `level` is the last one before memory
+    fn next_level(&self, level: usize) -> Option<usize> {
+        (level + 1..self.caches.len()).find(|&i| self.config.caches[i].enabled)
+    }
+
+    /// The previous level in use above `level`, or `None` if `level` is the first
+    fn prev_level(&self, level: usize) -> Option<usize> {
+        (0..level).rev().find(|&i| self.config.caches[i].enabled)
+    }
+
+    /// Whether the next level in use below `level` is exclusive of it
+    fn next_is_exclusive(&self, level: usize) -> bool {
+        matches!(self.next_level(level), Some(next) if self.config.caches[next].inclusion == Inclusion::Exclusive)
+    }
+
+    /// Whether lines evicted from `level` have to wait until the rest of the access has looked
+    /// for its line, because they are about to be swapped with it
+    fn swaps_victims(&self, level: usize) -> bool {
+        (level == 0 && self.victim.is_some()) || self.next_is_exclusive(level)
+    }
+
+    /// Deals with a line evicted from `level`. Lines evicted from the DC go to its victim cache
+    /// if it has one, and only leave the DC side of the hierarchy once they fall out of that.
+    fn evict(&mut self, level: usize, addr: u64, dirty: bool, writebacks: &mut Writebacks) {
+        if let (0, Some(victim)) = (level, self.victim.as_mut()) {
+            let response = victim.fill_victim(addr, dirty);
+            if let Some(evicted_addr) = response.eviction {
+                self.evict_below(level, evicted_addr, response.writeback.is_some(), writebacks);
+            }
+        } else {
+            self.evict_below(level, addr, dirty, writebacks);
+        }
+    }
+
+    /// Deals with a line leaving `level`. If the level below is exclusive, the line moves down
+    /// into it; otherwise it is written back only if dirty. Levels above lose their copy if
+    /// `level` is inclusive, and a dirty copy among those makes the line leave dirty.
+    fn evict_below(&mut self, level: usize, addr: u64, dirty: bool, writebacks: &mut Writebacks) {
+        let dirty = self.back_invalidate(level, addr, writebacks) || dirty;
+        if dirty {
+            writebacks.caches[level] += 1;
+        }
+        match self.next_level(level) {
+            Some(next) if self.next_is_exclusive(level) => {
+                let response = self.caches[next].fill_victim(addr, dirty);
+                if let Some(evicted_addr) = response.eviction {
+                    self.evict(next, evicted_addr, response.writeback.is_some(), writebacks);
+                }
+            },
+            _ if dirty => self.write_back(level, addr, writebacks),
+            _ => (),
+        }
+    }
+
+    /// Writes a dirty line evicted from `level` into the level below, which may in turn evict
+    /// (and write back) a line of its own. Past the last level, the line goes to memory.
+    fn write_back(&mut self, level: usize, addr: u64, writebacks: &mut Writebacks) {
+        match self.next_level(level) {
+            Some(next) => {
+                let response = self.caches[next].write_force(addr);
+                if let Some(evicted_addr) = response.eviction {
+                    self.evict(next, evicted_addr, response.writeback.is_some(), writebacks);
+                }
+            },
+            None => self.access_memory(addr),
+        }
+    }
+
+    /// Sends a request for the line at `addr` to main memory, through DRAM if it is modeled.
+    fn access_memory(&mut self, addr: u64) {
+        self.stats.main_memory_refs += 1;
+        let (dram, stats) = match (self.dram.as_mut(), self.stats.dram.as_mut()) {
+            (Some(dram), Some(stats)) => (dram, stats),
+            _ => return,
+        };
+        let response = dram.access(addr);
+        let bank = &mut stats.banks[response.bank];
+        bank.requests += 1;
+        bank.busy += response.cycles;
+        match response.result {
+            RowResult::Hit => bank.row_hits += 1,
+            RowResult::Empty => bank.row_empty += 1,
+            RowResult::Conflict => bank.row_conflicts += 1,
+        }
+        self.dram_cycles += response.cycles;
+    }
+
+    /// Puts a write the DC sends on below into the write buffer, combining it with a waiting
+    /// write to the same line if there is one. A full buffer first drains its oldest line,
+    /// which stalls the write. Returns whether it stalled.
+    fn buffer_write(&mut self, addr: u64, writebacks: &mut Writebacks) -> bool {
+        let write_buffer = match self.write_buffer.as_mut() {
+            Some(write_buffer) => write_buffer,
+            None => return false,
+        };
+        let stalled = !write_buffer.holds(addr) && write_buffer.is_full();
+        let oldest = if stalled { write_buffer.pop() } else { None };
+        if let Some(line) = oldest {
+            self.drain_line(line, writebacks);
+        }
+        let coalesced = self.write_buffer.as_mut().map_or(false, |write_buffer| write_buffer.push(addr));
+        if let Some(stats) = self.stats.write_buffer.as_mut() {
+            stats.writes += 1;
+            stats.coalesced += coalesced as u64;
+            stats.stalls += stalled as u64;
+        }
+        stalled
+    }
+
+    /// Drains the write buffer as far as its drain policy says to after an access. `idle` says
+    /// the access left the level below the DC alone, which is when an eager buffer drains.
+    fn drain_write_buffer(&mut self, idle: bool, writebacks: &mut Writebacks) {
+        let (drain, waiting) = match self.write_buffer.as_ref() {
+            Some(write_buffer) => (write_buffer.config.drain, write_buffer.len()),
+            None => return,
+        };
+        let lines = match drain {
+            DrainPolicy::Eager if idle => waiting.min(1),
+            DrainPolicy::Eager => 0,
+            DrainPolicy::Threshold(threshold) => (waiting + 1).saturating_sub(threshold as usize),
+            DrainPolicy::Full => 0,
+        };
+        for _ in 0..lines {
+            if let Some(line) = self.write_buffer.as_mut().and_then(WriteBuffer::pop) {
+                self.drain_line(line, writebacks);
+            }
+        }
+    }
+
+    /// Writes a line from the write buffer to the levels below the DC, going on down through
+    /// write-through levels and write misses that don't allocate, and to memory past the last.
+    fn drain_line(&mut self, addr: u64, writebacks: &mut Writebacks) {
+        if let Some(stats) = self.stats.write_buffer.as_mut() {
+            stats.drained += 1;
+        }
+        let mut next = self.next_level(0);
+        while let Some(level) = next {
+            let response = if self.config.caches[level].inclusion == Inclusion::Exclusive {
+                self.caches[level].write_around(addr)
+            } else {
+                self.caches[level].write(addr)
+            };
+            if let Some(evicted_addr) = response.eviction {
+                self.evict(level, evicted_addr, response.writeback.is_some(), writebacks);
+            }
+            if response.result == QueryResult::Hit && self.config.caches[level].write_policy == WriteBack {
+                return;
+            }
+            next = self.next_level(level);
+        }
+        self.access_memory(addr);
+    }
+
+    /// Invalidates a line evicted from an inclusive `level` in the level above, and so on up
+    /// for as long as the levels are inclusive. A level with no inclusion policy named only
+    /// invalidates copies (in every level above) filled from the very same address. Dirty
+    /// copies count as writebacks from their level; returns whether there were any, since
+    /// their data now leaves with the evicted line.
+    fn back_invalidate(&mut self, level: usize, addr: u64, writebacks: &mut Writebacks) -> bool {
+        let mut dirty = false;
+        match self.config.caches[level].inclusion {
+            Inclusion::Legacy => {
+                for above in (0..level).rev() {
+                    let mut removed: Vec<CacheEntry> = self.caches[above].clean_exact(addr).into_iter().collect();
+                    if above == 0 {
+                        removed.extend(self.victim.as_mut().and_then(|vc| vc.clean_exact(addr)));
+                        removed.extend(self.parked.iter_mut().flatten().filter_map(|p| p.dc.clean_exact(addr)));
+                    }
+                    dirty |= self.count_back_invalidations(level, above, &removed, false, writebacks);
+                }
+            },
+            Inclusion::Inclusive => if let Some(above) = self.prev_level(level) {
+                // The evicted line may span several (smaller) lines of the level above
+                let line_size = self.config.caches[level].line_size as u64;
+                let block_start = addr & !(line_size - 1);
+                for line_addr in (block_start..block_start + line_size).step_by(self.config.caches[above].line_size as usize) {
+                    let mut removed: Vec<CacheEntry> = self.caches[above].clean_addr(line_addr).into_iter().collect();
+                    // The victim cache counts as part of the DC, and every core has its own DC
+                    if above == 0 {
+                        removed.extend(self.victim.as_mut().and_then(|vc| vc.clean_addr(line_addr)));
+                        removed.extend(self.parked.iter_mut().flatten().filter_map(|p| p.dc.clean_addr(line_addr)));
+                    }
+                    let dirty_above = self.back_invalidate(above, line_addr, writebacks);
+                    dirty |= self.count_back_invalidations(level, above, &removed, dirty_above, writebacks);
+                }
+            },
+            Inclusion::Exclusive | Inclusion::Nine => (),
+        }
+        dirty
+    }
+
+    /// Counts the copies of a line evicted from `level` that were invalidated in `above`, and
+    /// the writebacks of those that were dirty or took dirty data from further up. Returns
+    /// whether any dirty data came down.
+    fn count_back_invalidations(
+        &mut self,
+        level: usize,
+        above: usize,
+        removed: &[CacheEntry],
+        dirty_above: bool,
+        writebacks: &mut Writebacks,
+    ) -> bool {
+        if let Some(inclusion) = self.stats.inclusion.as_mut() {
+            inclusion.back_invalidations[level] += removed.len() as u64;
+        }
+        let dirty = removed.iter().filter(|entry| entry.is_dirty() || dirty_above).count() as u64;
+        writebacks.caches[above] += dirty;
+        dirty > 0 || dirty_above
+    }
+
+    /// Fills whatever lines the prefetcher at `level` asks for after seeing a demand access.
+    /// Each line comes from the first level below that has it, or from memory.
+    fn prefetch(&mut self, level: usize, pc: Option<u64>, addr: u64, result: QueryResult, writebacks: &mut Writebacks) {
+        for line_addr in self.caches[level].prefetch_candidates(pc, addr, result) {
+            // A DC doesn't prefetch lines another core's DC holds, which would take snooping
+            if level == 0 && self.parked.iter().flatten().any(|p| p.dc.holds(line_addr)) {
+                continue;
+            }
+            let below = (level + 1..self.caches.len())
+                .any(|i| self.config.caches[i].enabled && self.caches[i].holds(line_addr));
+            if !below {
+                self.access_memory(line_addr);
+            }
+            let response = self.caches[level].prefetch(line_addr);
+            if let Some(evicted_addr) = response.eviction {
+                self.evict(level, evicted_addr, response.writeback.is_some(), writebacks);
+            }
+        }
+    }
+
+    /// Invalidates a page that was evicted from memory in every cache. Dirty lines are written
+    /// back through each level below (merging with that level's copy if it has one) and on to
+    /// memory.
+    fn flush_page(&mut self, ppn: u64) -> Writebacks {
+        let mut writebacks = Writebacks::new(self.caches.len());
+        // Buffered writes to the page have to get below the DC before it is cleaned out
+        let buffered = self.write_buffer.as_mut()
+            .map_or_else(Vec::new, |wb| wb.take_page(ppn, self.config.pt.offset_size));
+        for addr in buffered {
+            self.drain_line(addr, &mut writebacks);
+        }
+        // Dirty lines on their way down from the levels above
+        let mut carried = Vec::<u64>::new();
+
+        for level in self.enabled_levels() {
+            let cache = &mut self.caches[level];
+            // Lines this level doesn't have pass straight through it
+            carried.retain(|&addr| !cache.absorb_writeback(addr));
+            carried.append(&mut cache.clean_ppn(ppn).unwrap_or_default());
+            if let (0, Some(victim)) = (level, self.victim.as_mut()) {
+                carried.append(&mut victim.clean_ppn(ppn).unwrap_or_default());
+            }
+            if level == 0 {
+                for parked in self.parked.iter_mut().flatten() {
+                    carried.append(&mut parked.dc.clean_ppn(ppn).unwrap_or_default());
+                }
+            }
+            writebacks.caches[level] = carried.len() as u64;
+        }
+        for addr in carried {
+            self.access_memory(addr);
+        }
+
+        writebacks
+    }
+
+    /// Sends an access down the cache hierarchy (DC -> L2 -> ... -> Memory), stopping at the
+    /// first level that can satisfy it.
+    /// `from_peer` says another core's DC hands over the line, so a DC miss goes no further.
+    fn access_caches(&mut self, access_event: AccessEvent, pc: Option<u64>, from_peer: bool, writebacks: &mut Writebacks) -> CacheAccess {
+        let mut cache_responses: Vec<Option<CacheResponse>> = self.caches.iter().map(|_| None).collect();
+        // What still has to go further down: misses, and writes that hit in a write-through level
+        let mut demand = Some(access_event);
+        // The level that filled a line for this access, if one has so far
+        let mut filled: Option<usize> = None;
+        // Lines evicted on their way into an exclusive level, which has to be checked first
+        let mut victims = Vec::new();
+        let mut victim_result = None;
+        let mut stalled = false;
+
+        for level in self.enabled_levels() {
+            let event = match demand.take() {
+                Some(event) => event,
+                None => break,
+            };
+            let exclusive = self.config.caches[level].inclusion == Inclusion::Exclusive
+                && self.prev_level(level).is_some();
+            let response = match event {
+                // A hit moves the line up into the level that just filled it
+                _ if exclusive && filled.is_some() => {
+                    let (response, line) = self.caches[level].take(event.addr());
+                    if let Some(line) = line {
+                        if let Some(inclusion) = self.stats.inclusion.as_mut() {
+                            inclusion.exclusive_swaps[level] += 1;
+                        }
+                        if let (true, Some(above)) = (line.is_dirty(), filled) {
+                            self.caches[above].absorb_writeback(event.addr());
+                        }
+                    }
+                    response
+                },
+                AccessEvent::Write(addr) if exclusive => self.caches[level].write_around(addr),
+                AccessEvent::Read(addr) => self.caches[level].read(addr),
+                AccessEvent::Write(addr) => self.caches[level].write(addr),
+            };
+            if let Some(evicted_addr) = response.eviction {
+                let dirty = response.writeback.is_some();
+                if self.swaps_victims(level) {
+                    victims.push((level, evicted_addr, dirty));
+                } else {
+                    self.evict(level, evicted_addr, dirty, writebacks);
+                }
+            }
+            if filled.is_none() && !exclusive && response.result == QueryResult::Miss
+                && (!event.is_write() || self.config.caches[level].write_miss_policy == WriteAllocate) {
+                filled = Some(level);
+            }
+            demand = match response.result {
+                QueryResult::Miss => Some(event),
+                QueryResult::Hit if event.is_write() && self.config.caches[level].write_policy == WriteThrough => {
+                    Some(event)
+                },
+                QueryResult::Hit => None,
+            };
+            if level == 0 && from_peer && filled == Some(0) {
+                demand = None;
+            }
+            cache_responses[level] = Some(response);
+
+            // A DC miss checks the victim cache before going any further. A hit swaps the line
+            // back into the DC with the one the DC just evicted.
+            if let (0, Some(_), Some(victim)) = (level, filled, self.victim.as_mut()) {
+                let (response, line) = victim.take(event.addr());
+                if let Some(line) = line {
+                    if line.is_dirty() {
+                        self.caches[0].absorb_writeback(event.addr());
+                    }
+                    demand = None;
+                }
+                victim_result = Some(response.result);
+            }
+
+            // What the DC sends on below goes into its write buffer instead, and a read miss
+            // takes its line from there if a write to it is waiting
+            if let (0, Some(write_buffer)) = (level, self.write_buffer.as_ref()) {
+                match demand {
+                    Some(AccessEvent::Write(addr)) => {
+                        stalled = self.buffer_write(addr, writebacks);
+                        demand = None;
+                    },
+                    Some(AccessEvent::Read(addr)) if write_buffer.holds(addr) => {
+                        if let Some(stats) = self.stats.write_buffer.as_mut() {
+                            stats.forwarded += 1;
+                        }
+                        demand = None;
+                    },
+                    _ => (),
+                }
+            }
+        }
+        for (level, addr, dirty) in victims {
+            self.evict(level, addr, dirty, writebacks);
+        }
+        // Prefetchers see the demand access once it is done with their level
+        for (level, response) in cache_responses.iter().enumerate() {
+            if let Some(response) = response {
+                self.prefetch(level, pc, access_event.addr(), response.result, writebacks);
+            }
+        }
+
+        // Main memory sees whatever falls out of the last enabled cache level
+        let memory = demand.is_some();
+        if let Some(event) = demand {
+            self.access_memory(event.addr());
+        }
+
+        for (counter, response) in self.stats.caches.iter_mut().zip(cache_responses.iter()) {
+            if let Some(response) = response {
+                counter.record(response.result);
+            }
+        }
+        if let (Some(counter), Some(result)) = (self.stats.victim.as_mut(), victim_result) {
+            counter.record(result);
+        }
+
+        CacheAccess { responses: cache_responses, victim: victim_result, memory, stalled }
+    }
+
+    /// The cycles an access spent in each cache level it reached (a victim cache probe costs as
+    /// much as the DC) and in memory, if accesses are timed.
+    fn cache_latency(&self, cache_access: &CacheAccess) -> Latency {
+        let mut cycles = Latency::new(self.caches.len());
+        if let Some(latency) = &self.config.latency {
+            for (level, response) in cache_access.responses.iter().enumerate() {
+                if response.is_some() {
+                    cycles.caches[level] += latency.caches[level];
+                }
+            }
+            if cache_access.victim.is_some() {
+                cycles.caches[0] += latency.caches[0];
+            }
+            if cache_access.memory {
+                cycles.memory += self.memory_latency();
+            }
+            // A stalled write waits for the oldest buffered line to be written to the next level
+            if cache_access.stalled {
+                match self.next_level(0) {
+                    Some(next) => cycles.caches[next] += latency.caches[next],
+                    None => cycles.memory += self.memory_latency(),
+                }
+            }
+        }
+        cycles
+    }
+
+    /// The cycles a whole access took, once it is done: the TLB lookups, a page walk (whose PTE
+    /// reads took `pte_cycles`) and any page fault, the caches and memory, and every dirty line
+    /// written back on the way, which costs as much as the level it goes into.
+    fn access_latency(
+        &self,
+        tlb: bool,
+        stlb: bool,
+        pt_response: Option<&PageTableResponse>,
+        pte_cycles: u64,
+        cache_access: &CacheAccess,
+        writebacks: &Writebacks,
+    ) -> Option<Latency> {
+        let latency = self.config.latency.as_ref()?;
+        let mut cycles = self.cache_latency(cache_access);
+        cycles.tlb = if tlb { latency.tlb } else { 0 };
+        cycles.stlb = if stlb { latency.stlb } else { 0 };
+        if let Some(pt) = pt_response {
+            cycles.walk = latency.walk + pte_cycles;
+            cycles.disk = latency.disk * (pt.disk_reads + pt.disk_writes) as u64;
+        }
+        for (level, &n) in writebacks.caches.iter().enumerate() {
+            match self.next_level(level) {
+                Some(next) => cycles.caches[next] += n * latency.caches[next],
+                None => cycles.memory += n * self.memory_latency(),
+            }
+        }
+        // DRAM's time depends on each request, so it is added up as they are made
+        cycles.memory += self.dram_cycles;
+        Some(cycles)
+    }
+
+    /// The fixed cycles of a request to main memory, which DRAM replaces with its own timing.
+    fn memory_latency(&self) -> u64 {
+        match (&self.config.latency, &self.dram) {
+            (Some(latency), None) => latency.memory,
+            _ => 0,
+        }
     }
 
-    /// Issue an access event to the memory system (which is either a read or a write).
+    /// Reads the PTEs of a page table walk for `addr`, either through the caches or straight
+    /// from memory. A flat page table doesn't read any.
+    ///
+    /// The walk caches are probed from the deepest upper level up; a hit hands over the
+    /// entry at that level, so the walk skips it and every level above.
+    fn walk_page_table(&mut self, addr: u64, writebacks: &mut Writebacks) -> u64 {
+        let steps = self.pt.walk(addr);
+        let walk = match self.stats.walk.as_mut() {
+            Some(walk) => walk,
+            None => return 0,
+        };
+        walk.walks += 1;
+
+        // A huge page's walk ends early, and its leaf entry isn't kept in a walk cache
+        let upper_levels = self.walk_caches.len().min(steps.len() - 1);
+        let mut start = 0;
+        for level in (0..upper_levels).rev() {
+            if let Some(walk_cache) = self.walk_caches[level].as_mut() {
+                let result = walk_cache.lookup(steps[level].vpn_prefix).result;
+                if let Some(counter) = walk.caches[level].as_mut() {
+                    counter.record(result);
+                }
+                if result == QueryResult::Hit {
+                    start = level + 1;
+                    break;
+                }
+            }
+        }
+
+        // The entries read at each upper level point to the table below them
+        for level in start..upper_levels {
+            if let Some(walk_cache) = self.walk_caches[level].as_mut() {
+                walk_cache.push(steps[level].vpn_prefix, steps[level + 1].pte_addr, 0);
+            }
+        }
+
+        walk.pte_reads += (steps.len() - start) as u64;
+        let cached = matches!(self.config.pt.radix, Some(radix) if radix.cached);
+        let mut cycles = 0;
+        for step in steps[start..].iter() {
+            if cached {
+                let cache_access = self.access_caches(AccessEvent::Read(step.pte_addr), None, false, writebacks);
+                cycles += self.cache_latency(&cache_access).total();
+            } else {
+                self.access_memory(step.pte_addr);
+                cycles += self.memory_latency();
+            }
+        }
+        cycles
+    }
+
+    /// Issue an access event to the memory system (which is either a read or a write) from a
+    /// core, along with the PC of the instruction that made it if the trace has one.
     pub fn access(
         &mut self, 
+        core: usize,
         raw_access_type: char, 
-        raw_addr: u32
+        raw_addr: u64,
+        pc: Option<u64>,
     ) -> Result<MemoryResponse, Box<dyn std::error::Error>> {
 
+        if core >= self.parked.len() {
+            error!("core {} doesn't exist (there are {} cores)", core, self.parked.len());
+        }
+        self.switch_to(core);
+        let pid = self.pids[core];
+        self.enter_process(pid);
+
         // Make sure addr is a reasonable size
+        if self.config.address_bits < 64 && raw_addr >> self.config.address_bits != 0 {
+            error!("address {:x} is wider than {} bits", raw_addr, self.config.address_bits);
+        }
         match self.config.address_type {
             config::AddressType::Virtual => {
                 if raw_addr > self.config.pt.max_virtual_addr - 1 {
@@ -100,23 +1020,33 @@ impl Memory {
             }
         }
 
+        let is_write = AccessEvent::from_raw(raw_access_type, raw_addr)?.is_write();
+        let mut writebacks = Writebacks::new(self.caches.len());
+
         /* Step 1: Translate virtual address to physical address */
 
+        // Cycles spent reading PTEs, if the page table is walked
+        let mut pte_cycles = 0;
+        self.dram_cycles = 0;
+
         let translation_response = match self.config.address_type {
             config::AddressType::Physical => {
                 let (ppn, page_offset) = bits::split_at(raw_addr, self.config.pt.offset_size);
                 TranslationResponse {
                     ppn,
                     page_offset,
+                    page_shift: 0,
                     vpn: None,
                     pt_response: None,
                     tlb_response: None,
+                    stlb_response: None,
                 }
             },
             config::AddressType::Virtual => {
                 let ppn;
                 let vpn;
                 let page_offset;
+                let page_shift;
 
                 // FIXING THE CODE FOR SHIVAM: rustc 1.58 does not support this
                 //let optional_tlb_response = self.config.tlb.enabled.then_some(self.tlb.lookup(raw_addr));
@@ -127,40 +1057,66 @@ impl Memory {
                     None
                 };
 
-                let optional_pt_response = match optional_tlb_response {
+                // The STLB is only probed when the data TLB misses
+                let optional_stlb_response = match (&optional_tlb_response, self.stlb.as_mut()) {
+                    (Some(tlb_response), Some(stlb)) if tlb_response.result == QueryResult::Miss => {
+                        Some(stlb.lookup(raw_addr))
+                    },
+                    _ => None,
+                };
+
+                let optional_pt_response = match (&optional_tlb_response, &optional_stlb_response) {
                     // TLB Disabled: go to page table
-                    None => Some(self.pt.translate(raw_addr)),
+                    (None, _) => {
+                        pte_cycles = self.walk_page_table(raw_addr, &mut writebacks);
+                        Some(self.pt.translate(raw_addr, is_write)?)
+                    },
+                    // STLB hit: refill the data TLB, no need to access page table
+                    (Some(_), Some(stlb_response)) if stlb_response.result == QueryResult::Hit => {
+                        self.pt.translate(raw_addr, is_write)?;
+                        self.tlb.push(stlb_response.vpn, stlb_response.ppn.unwrap(), stlb_response.page_shift);
+                        None
+                    },
                     // TLB Miss: go to page table and then update tlb
-                    Some(ref tlb_response) if tlb_response.result == QueryResult::Miss => {
-                        let pt_response = self.pt.translate(raw_addr);
+                    (Some(tlb_response), _) if tlb_response.result == QueryResult::Miss => {
+                        pte_cycles = self.walk_page_table(raw_addr, &mut writebacks);
+                        let pt_response = self.pt.translate(raw_addr, is_write)?;
 
-                        if let Some(evicted_ppn) = pt_response.evicted_ppn {
-                            // must invalidated tlb entries before inserting a new entry
-                            self.tlb.clean_ppn(evicted_ppn);
+                        // must invalidated tlb entries before inserting a new entry
+                        for &evicted_ppn in pt_response.evicted_ppns.iter() {
+                            self.clean_tlbs(evicted_ppn);
+                        }
+                        self.tlb.push(pt_response.vpn, pt_response.ppn, pt_response.page_shift);
+                        if let Some(stlb) = self.stlb.as_mut() {
+                            stlb.push(pt_response.vpn, pt_response.ppn, pt_response.page_shift);
                         }
-                        self.tlb.push(pt_response.vpn, pt_response.ppn);
 
                         Some(pt_response)
                     }
                     // TLB hit: No need to access page table
-                    Some(_/* TLB hit */) => { self.pt.translate(raw_addr); None}
+                    (Some(_/* TLB hit */), _) => { self.pt.translate(raw_addr, is_write)?; None}
                 };
 
                 // Invalidate entries in L2, DC, TLB, if a PTE was evicted
-                if let Some(evicted_ppn) = optional_pt_response.as_ref().map(|ptr| ptr.evicted_ppn).flatten() {
-                    self.dc.clean_ppn(evicted_ppn);
-                    self.l2.clean_ppn(evicted_ppn);
+                let evicted_ppns = optional_pt_response.as_ref().map_or_else(Vec::new, |ptr| ptr.evicted_ppns.clone());
+                for evicted_ppn in evicted_ppns {
+                    writebacks += &self.flush_page(evicted_ppn);
                 }
 
                 // Get ppn, vpn, and page_offset for reporting 
-                if let Some(tlb_response) = optional_tlb_response.as_ref().filter(|t| t.result == QueryResult::Hit) {
+                let tlb_hit = optional_tlb_response.as_ref()
+                    .filter(|t| t.result == QueryResult::Hit)
+                    .or_else(|| optional_stlb_response.as_ref().filter(|t| t.result == QueryResult::Hit));
+                if let Some(tlb_response) = tlb_hit {
                     ppn = tlb_response.ppn.unwrap();
                     vpn = tlb_response.vpn;
                     page_offset = tlb_response.page_offset;
+                    page_shift = tlb_response.page_shift;
                 } else if let Some(ref pt_response) = optional_pt_response {
                     ppn = pt_response.ppn;
                     vpn = pt_response.vpn;
                     page_offset = pt_response.page_offset;
+                    page_shift = pt_response.page_shift;
                 } else {
                     panic!("Somehow, neither the page table nor the tlb produced any ppn, vpn, or page_offset")
                 }
@@ -168,74 +1124,116 @@ impl Memory {
                 TranslationResponse {
                     ppn,
                     page_offset,
+                    page_shift,
                     vpn: Some(vpn),
                     pt_response: optional_pt_response,
                     tlb_response: optional_tlb_response,
+                    stlb_response: optional_stlb_response,
                 }
             },
         };
 
-        let (pt_response, tlb_response) = (translation_response.pt_response, translation_response.tlb_response);
+        let (pt_response, tlb_response, stlb_response) =
+            (translation_response.pt_response, translation_response.tlb_response, translation_response.stlb_response);
 
         // create the physical addr from the ppn and page offset
         let physical_addr = bits::join_at(translation_response.ppn, translation_response.page_offset, self.config.pt.offset_size);
         let access_event = AccessEvent::from_raw(raw_access_type, physical_addr)?;
 
-        /* Step 2: Try to access data in caches in the order of DC -> L2 -> Memory */
+        /* Step 2: Try to access data in caches in the order of DC -> L2 -> ... -> Memory */
 
-        let dc_response = match access_event {
-            AccessEvent::Read(addr) => self.dc.read(addr),
-            AccessEvent::Write(addr) => self.dc.write(addr),
-        };
-        if let Some(writeback_addr) = dc_response.writeback {
-            let l2 = self.l2.write_force(writeback_addr);
-            if let Some(evicted_addr) = l2.eviction {
-                // if an address was evicted from L2, invalidate it in L1
-                self.dc.clean_addr(evicted_addr);
-            }
+        let snoop = self.snoop(access_event, &mut writebacks);
+        let cache_access = self.access_caches(access_event, pc, snoop.from_peer, &mut writebacks);
+        if let Some(fill) = snoop.fill {
+            self.settle(physical_addr, fill, is_write, &mut writebacks);
         }
+        let idle = !is_write && !cache_access.memory && cache_access.responses.iter().skip(1).all(Option::is_none);
+        self.drain_write_buffer(idle, &mut writebacks);
 
-        let l2_response: Option<cache::CacheResponse> = if self.config.l2.enabled {
-            match dc_response.result {
-                // If DC has a write through policy, then we write through to L2
-                QueryResult::Hit if access_event.is_write() && self.config.dc.write_policy == WriteThrough => {
-                    Some(self.l2.write(access_event.addr()))
-                },
-                QueryResult::Miss => {
-                    Some( match access_event {
-                        AccessEvent::Read(addr) => self.l2.read(addr),
-                        AccessEvent::Write(addr) => self.l2.write(addr),
-                    })
-                },
-                _ => None,
-            }
-        } else {
-            None
-        };
+        /* Step 3: Tally up the results */
 
-        if let Some(l2) = &l2_response {
-            if let Some(evicted_addr) = l2.eviction {
-                // if an address was evicted from L2, invalidate it in L1
-                self.dc.clean_addr(evicted_addr);
+        if is_write {
+            self.stats.writes += 1;
+        } else {
+            self.stats.reads += 1;
+        }
+        if let Some(tlb) = &tlb_response {
+            self.stats.dtlb.record(tlb.result);
+        }
+        if let (Some(counter), Some(stlb)) = (self.stats.stlb.as_mut(), &stlb_response) {
+            counter.record(stlb.result);
+        }
+        if let Some(pt) = &pt_response {
+            self.stats.pt.record(pt.res);
+            self.stats.page_table_refs += 1;
+            if pt.res == QueryResult::Miss {
+                self.stats.disk_refs += 1;
             }
+            writebacks.disk += pt.disk_writes as u64;
+            if let Some(swap) = self.stats.swap.as_mut() {
+                match (pt.res, pt.disk_reads) {
+                    (QueryResult::Hit, _) => (),
+                    (QueryResult::Miss, 0) => swap.minor_faults += 1,
+                    (QueryResult::Miss, _) => swap.major_faults += 1,
+                }
+                swap.disk_reads += pt.disk_reads as u64;
+                swap.disk_writes += pt.disk_writes as u64;
+            }
+        }
+        if let Some(processes) = self.stats.processes.as_mut() {
+            let counts = processes.processes.entry(pid).or_default();
+            counts.refs += 1;
+            if let Some(tlb) = &tlb_response {
+                counts.dtlb.record(tlb.result);
+            }
+            if let Some(pt) = &pt_response {
+                counts.pt.record(pt.res);
+            }
+        }
+        if let Some(huge) = self.stats.huge_pages.as_mut() {
+            let size = huge.shifts.iter().position(|&shift| shift == translation_response.page_shift).unwrap_or(0);
+            huge.refs[size] += 1;
+            if matches!(pt_response, Some(ref pt) if pt.promoted) {
+                huge.promotions += 1;
+            }
+        }
+        let latency = self.access_latency(tlb_response.is_some(), stlb_response.is_some(),
+            pt_response.as_ref(), pte_cycles, &cache_access, &writebacks);
+        if let (Some(total), Some(cycles)) = (self.stats.latency.as_mut(), &latency) {
+            *total += cycles;
+        }
+        if let Some(total) = self.stats.writebacks.as_mut() {
+            *total += &writebacks;
+        }
+        if let (Some(coherence), Some(dc)) = (self.stats.coherence.as_mut(), &cache_access.responses[0]) {
+            coherence.cores[self.core].dc.record(dc.result);
+        }
+        if let Some(oracle) = &self.oracle {
+            oracle.tick();
         }
 
         let mem_response = MemoryResponse {
             addr: raw_addr,
+            addr_digits: ((self.config.address_bits + 3) / 4) as usize,
+            physical_addr,
             page_offset: translation_response.page_offset,
             vpn: translation_response.vpn,
+            asid: pid,
             ppn: translation_response.ppn,
             page_table_res: pt_response.as_ref().map(|r| r.res),
+            page_size: self.config.pt.huge.map(|_| self.config.pt.page_size << translation_response.page_shift),
+            page_shift: translation_response.page_shift,
             tlb_tag: tlb_response.as_ref().map(|r| r.tag),
             tlb_idx: tlb_response.as_ref().map(|r| r.idx),
             tlb_res: tlb_response.as_ref().map(|r| r.result),
-            dc_tag: dc_response.tag,
-            dc_idx: dc_response.idx,
-            dc_res: Some(dc_response.result),
-            l2_tag: l2_response.as_ref().map(|r| r.tag),
-            l2_idx: l2_response.as_ref().map(|r| r.idx),
-            l2_res: l2_response.as_ref().map(|r| r.result),
-            ..Default::default()
+            stlb: self.stlb.as_ref().map(|_| stlb_response.as_ref().map(|r| (r.tag, r.idx, r.result))),
+            caches: cache_access.responses.iter()
+                .map(|r| r.as_ref().map(|r| (r.tag, r.idx, r.result)))
+                .collect(),
+            victim: self.victim.as_ref().map(|_| cache_access.victim),
+            writebacks: Some(writebacks).filter(|_| self.config.report_writebacks),
+            coherence: self.stats.coherence.as_ref().map(|_| (core, self.caches[0].line_state(physical_addr))),
+            cycles: latency.map(|cycles| cycles.total()),
         };
 
         Ok(mem_response)
@@ -245,28 +1243,62 @@ impl Memory {
 /// Details the interior behavior of a simulated access to the memory system.
 #[derive(Default)]
 pub struct MemoryResponse {
-    addr: u32,
-    vpn: Option<u32>,
-    ppn: u32,
-    page_offset: u32,
-    tlb_tag: Option<u32>,
-    tlb_idx: Option<u32>,
+    addr: u64,
+    /// Hex digits the address is printed with, enough for the widest address
+    addr_digits: usize,
+    physical_addr: u64,
+    vpn: Option<u64>,
+    /// Address space the access was translated in
+    asid: u32,
+    ppn: u64,
+    page_offset: u64,
+    tlb_tag: Option<u64>,
+    tlb_idx: Option<u64>,
     tlb_res: Option<QueryResult>,
+    /// Tag, index and result in the STLB, if there is one (and it was probed)
+    stlb: Option<Option<(u64, u64, QueryResult)>>,
     page_table_res: Option<QueryResult>,
+    /// Size of the page that translated the access, if there are huge pages
+    page_size: Option<u64>,
+    /// Base pages in that page, as a power of 2
+    page_shift: u32,
+
+    /// Tag, index and result at each configured cache level, if the access reached it
+    caches: Vec<Option<(u64, u64, QueryResult)>>,
+    /// Result of probing the victim cache, if there is one (and it was probed)
+    victim: Option<Option<QueryResult>>,
+
+    writebacks: Option<Writebacks>,
+    /// The core that made the access, and the state its DC's copy of the line was left in, if
+    /// there is more than one core
+    coherence: Option<(usize, LineState)>,
+    /// Cycles the access took, if accesses are timed
+    cycles: Option<u64>,
+}
+
+impl MemoryResponse {
+    /// The virtual page number, if the access was translated.
+    pub fn vpn(&self) -> Option<u64> {
+        self.vpn
+    }
+
+    /// What the TLBs know the access's translation by, if it was translated: the first vpn of
+    /// its page, and its ASID.
+    pub fn translation_key(&self) -> Option<u64> {
+        self.vpn.map(|vpn| tlb::translation_key(self.asid, vpn >> self.page_shift << self.page_shift))
+    }
 
-    dc_tag: u32,
-    dc_idx: u32,
-    dc_res: Option<QueryResult>,
-    l2_tag: Option<u32>,
-    l2_idx: Option<u32>,
-    l2_res: Option<QueryResult>,
+    /// The physical address the access resolved to.
+    pub fn physical_addr(&self) -> u64 {
+        self.physical_addr
+    }
 }
 
 impl std::fmt::Display for MemoryResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         write!(f, 
-            //addr  pg # pgoff tbtg tbix tlbr ptrs phypg dctag dcidx dcrs l2tg l2ix l2rs
-            "{:08x} {:6} {:4x} {:6} {:3} {:4} {:4} {:4x} {:6x} {:3x} {:4} {:6} {:3} {:4}",
+            //addr  pg # pgoff tbtg tbix tlbr
+            "{:0width$x} {:6} {:4x} {:6} {:3} {:4}",
 
             self.addr,
             self.vpn.map_or("".to_string(), |n| format!("{:6x}", n)),
@@ -274,15 +1306,49 @@ impl std::fmt::Display for MemoryResponse {
             self.tlb_tag.map_or("".to_string(), |n| format!("{:6x}", n)),
             self.tlb_idx.map_or("".to_string(), |n| format!("{:3x}", n)),
             self.tlb_res.as_ref().map_or("", |q| q.as_str()),
+            width = self.addr_digits,
+        )?;
+        if let Some(stlb) = self.stlb {
+            //     tag idx res
+            write!(f, " {:6} {:3} {:4}",
+                stlb.map_or("".to_string(), |(tag, _, _)| format!("{:6x}", tag)),
+                stlb.map_or("".to_string(), |(_, idx, _)| format!("{:3x}", idx)),
+                stlb.as_ref().map_or("", |(_, _, q)| q.as_str()),
+            )?;
+        }
+        write!(f,
+            //ptrs phypg
+            " {:4} {:4x}",
             self.page_table_res.as_ref().map_or("", |q| q.as_str()),
             self.ppn,
-            self.dc_tag,
-            self.dc_idx,
-            self.dc_res.as_ref().map_or("", |q| q.as_str()),
-            self.l2_tag.map_or("".to_string(), |n| format!("{:6x}", n)),
-            self.l2_idx.map_or("".to_string(), |n| format!("{:3x}", n)),
-            self.l2_res.as_ref().map_or("", |q| q.as_str()),
-        )
+        )?;
+        if let Some(page_size) = self.page_size {
+            write!(f, " {:>5}", bits::size_str(page_size))?;
+        }
+        for (level, cache) in self.caches.iter().enumerate() {
+            //     tag idx res
+            write!(f, " {:6} {:3} {:4}",
+                cache.map_or("".to_string(), |(tag, _, _)| format!("{:6x}", tag)),
+                cache.map_or("".to_string(), |(_, idx, _)| format!("{:3x}", idx)),
+                cache.as_ref().map_or("", |(_, _, q)| q.as_str()),
+            )?;
+            if let (0, Some(victim)) = (level, self.victim) {
+                write!(f, " {:4}", victim.as_ref().map_or("", |q| q.as_str()))?;
+            }
+        }
+        if let Some(wb) = &self.writebacks {
+            for n in wb.caches.iter() {
+                write!(f, " {:4}", n)?;
+            }
+            write!(f, " {:4}", wb.disk)?;
+        }
+        if let Some((core, state)) = self.coherence {
+            write!(f, " {:4} {:5}", core, state.as_str())?;
+        }
+        if let Some(cycles) = self.cycles {
+            write!(f, " {:>8}", cycles)?;
+        }
+        Ok(())
     }
 }
 
@@ -294,7 +1360,7 @@ pub enum QueryResult {
 }
 
 impl QueryResult {
-    #[allow(dead_code)]
+    #[allow(dead_code, clippy::inherent_to_string, clippy::wrong_self_convention)]
     fn to_string(&self) -> String {
         String::from(match self {
             QueryResult::Hit => "hit",
@@ -307,4 +1373,191 @@ impl QueryResult {
             QueryResult::Miss => "miss",
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A physically addressed DC (one set of two lines) over an L2 of 16 sets of four, with
+    /// `extended` appended
+    fn memory(name: &str, extended: &str) -> Memory {
+        let config = format!("\
+Data TLB configuration
+Number of sets: 4
+Set size: 2
+
+Page Table configuration
+Number of virtual pages: 64
+Number of physical pages: 16
+Page size: 256
+
+Data Cache configuration
+Number of sets: 1
+Set size: 2
+Line size: 16
+Write through/no write allocate: n
+
+L2 Cache configuration
+Number of sets: 16
+Set size: 4
+Line size: 16
+Write through/no write allocate: n
+
+Virtual addresses: n
+TLB: n
+L2 cache: y
+{}", extended);
+        Memory::new(parse(name, &config))
+    }
+
+    fn parse(name: &str, config: &str) -> Config {
+        let path = std::env::temp_dir().join(format!("memsim-test-{}-{}.config", name, std::process::id()));
+        std::fs::write(&path, config).unwrap();
+        let config = Config::from_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    /// Dirties line 0 in the DC only, keeps it there with DC hits while four other lines push
+    /// it out of its L2 set, and then reads it again. Returns whether that read hit in the DC.
+    fn evict_dirty_line_from_l2(mem: &mut Memory) -> bool {
+        let trace = [
+            ('R', 0x004), ('R', 0x010), ('R', 0x020), ('W', 0x008),
+            ('R', 0x100), ('R', 0x008), ('R', 0x200), ('R', 0x008),
+            ('R', 0x300), ('R', 0x008), ('R', 0x400),
+        ];
+        for (access, addr) in trace {
+            mem.access(0, access, addr, None).unwrap();
+        }
+        let response = mem.access(0, 'R', 0x008, None).unwrap();
+        matches!(response.caches[0], Some((_, _, QueryResult::Hit)))
+    }
+
+    #[test]
+    fn inclusive_back_invalidation_writes_back_dirty_lines() {
+        let mut mem = memory("inclusive", "\nL2 Cache extended configuration\nInclusion policy: inclusive\nReport writebacks: y\n");
+        assert!(!evict_dirty_line_from_l2(&mut mem));
+        let stats = mem.stats();
+        assert_eq!(stats.inclusion.unwrap().back_invalidations[1], 1);
+        assert_eq!(stats.writebacks.unwrap().caches, vec![1, 1]);
+    }
+
+    #[test]
+    fn default_back_invalidation_matches_the_exact_address() {
+        // The DC's copy was filled from 0x008 and the L2's from 0x004, so it stays
+        let mut mem = memory("default", "\nReport writebacks: y\n");
+        assert!(evict_dirty_line_from_l2(&mut mem));
+        assert_eq!(mem.stats().writebacks.unwrap().caches, vec![0, 1]);
+    }
+
+    /// A write-through DC that doesn't allocate on write misses, with a two-line write buffer
+    /// drained as `drain` says
+    fn buffered(name: &str, drain: &str) -> Memory {
+        memory(name, &format!("\nData Cache extended configuration\nWrite policy: write-through\n\
+            Write miss policy: no-write-allocate\nWrite buffer entries: 2\nWrite buffer drain: {}\n", drain))
+    }
+
+    #[test]
+    fn write_buffer_coalesces_stalls_and_forwards() {
+        let mut mem = buffered("buffer-full", "full");
+        for addr in [0x000, 0x004, 0x010, 0x020] {
+            mem.access(0, 'W', addr, None).unwrap();
+        }
+        // The write to 0x020 found both lines waiting and drained 0x000 to make room, and the
+        // read misses in the DC but finds its line in the buffer
+        let response = mem.access(0, 'R', 0x010, None).unwrap();
+        assert!(matches!(response.caches[0], Some((_, _, QueryResult::Miss))));
+        assert!(response.caches[1].is_none());
+        assert!(mem.caches[1].holds(0x000));
+        let stats = mem.stats().write_buffer.unwrap();
+        assert_eq!((stats.writes, stats.coalesced, stats.stalls, stats.forwarded), (4, 1, 1, 1));
+        assert_eq!(stats.drained, 3);
+    }
+
+    #[test]
+    fn eager_write_buffer_drains_after_idle_reads() {
+        let mut mem = buffered("buffer-eager", "eager");
+        mem.access(0, 'W', 0x000, None).unwrap();
+        mem.access(0, 'W', 0x010, None).unwrap();
+        // The first read goes to the L2, and only the DC hit after it leaves the L2 idle
+        mem.access(0, 'R', 0x100, None).unwrap();
+        assert_eq!(mem.write_buffer.as_ref().unwrap().len(), 2);
+        mem.access(0, 'R', 0x100, None).unwrap();
+        assert_eq!(mem.write_buffer.as_ref().unwrap().len(), 1);
+        assert!(mem.caches[1].holds(0x000) && !mem.caches[1].holds(0x010));
+    }
+
+    /// Fewest misses any replacement could give a fully associative cache of `ways` entries over
+    /// `keys`, by trying every victim at every miss.
+    fn belady_misses(keys: &[u64], held: &mut Vec<u64>, ways: usize) -> u64 {
+        let (&key, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => return 0,
+        };
+        if held.contains(&key) {
+            return belady_misses(rest, held, ways);
+        }
+        if held.len() < ways {
+            held.push(key);
+            let misses = belady_misses(rest, held, ways);
+            held.pop();
+            return 1 + misses;
+        }
+        (0..ways).map(|way| {
+            let evicted = std::mem::replace(&mut held[way], key);
+            let misses = belady_misses(rest, held, ways);
+            held[way] = evicted;
+            1 + misses
+        }).min().unwrap()
+    }
+
+    #[test]
+    fn opt_tlb_misses_match_belady_with_huge_pages() {
+        // A single two-entry TLB set, with vpns 0-7 in two huge pages of four base pages each
+        let config = parse("opt-huge", "\
+Data TLB configuration
+Number of sets: 1
+Set size: 2
+
+Page Table configuration
+Number of virtual pages: 64
+Number of physical pages: 16
+Page size: 256
+Huge page sizes: 1024
+Huge page frames: 2
+Huge page ranges: 0-7ff=1024
+
+Data Cache configuration
+Number of sets: 1
+Set size: 2
+Line size: 16
+Write through/no write allocate: n
+
+L2 Cache configuration
+Number of sets: 16
+Set size: 4
+Line size: 16
+Write through/no write allocate: n
+
+Virtual addresses: y
+TLB: y
+L2 cache: y
+
+Offline OPT: y
+");
+        let vpns = [0, 8, 1, 9, 4, 2, 8, 5, 10, 3, 9, 6, 0, 10, 7, 8, 1, 4];
+        let mut mem = Memory::new(config.clone());
+        let refs: Vec<_> = vpns.iter()
+            .map(|vpn| mem.access(0, 'R', vpn << 8, None).unwrap())
+            .map(|access| (access.translation_key(), access.physical_addr()))
+            .collect();
+
+        let mut opt_mem = Memory::with_oracle(config.clone(), Oracle::new(&config, refs.into_iter()));
+        for vpn in vpns {
+            opt_mem.access(0, 'R', vpn << 8, None).unwrap();
+        }
+        let pages: Vec<u64> = vpns.iter().map(|&vpn| if vpn < 8 { vpn & !3 } else { vpn }).collect();
+        assert_eq!(opt_mem.stats().dtlb.misses, belady_misses(&pages, &mut Vec::new(), 2));
+    }
+}
@@ -1,58 +1,89 @@
 use crate::{
     config::{self, WriteMissPolicy::*, WritePolicy::*},
     utils::bits,
-    memory::QueryResult,
+    memory::{
+        QueryResult,
+        DuelStats,
+        PrefetchStats,
+        replacement::{self, ReplacementPolicy},
+        prefetch::{self, Prefetcher},
+        coherence::LineState,
+    },
 };
 
 pub struct CacheResponse {
-    pub tag: u32,
-    pub idx: u32,
+    pub tag: u64,
+    pub idx: u64,
     pub result: QueryResult,
-    pub writeback: Option<u32>,
-    pub eviction: Option<u32>,
+    pub writeback: Option<u64>,
+    pub eviction: Option<u64>,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct CacheEntry {
-    tag: u32,
-    addr: u32,
-    ppn: u32,
+    tag: u64,
+    addr: u64,
+    ppn: u64,
     dirty: bool,
+    /// Filled by the prefetcher and not yet used by a demand access
+    prefetched: bool,
+    /// Another core's data cache may hold a copy too
+    shared: bool,
+    /// The shared copy that answers another core's miss (MESIF)
+    forward: bool,
 }
 
 impl CacheEntry {
     fn enfilthen(&mut self) {
         self.dirty = true;
     }
-    fn is_dirty(&self) -> bool {
+    pub fn is_dirty(&self) -> bool {
         self.dirty
     }
 }
 
 pub struct CPUCache {
-    sets: Vec<LRUSet>,
+    sets: Vec<Vec<Option<CacheEntry>>>,
+    policy: Box<dyn ReplacementPolicy>,
+    prefetcher: Option<Box<dyn Prefetcher>>,
+    prefetches: PrefetchStats,
     config: config::CacheConfig,
-    global_config: config::Config,
+    pt_config: config::PageTableConfig,
 }
 
 impl CPUCache {
-    pub fn new(config: config::CacheConfig, global_config: config::Config) -> Self {
-        let empty_set = LRUSet::new(config.set_entries as usize);
+    pub fn new(config: config::CacheConfig, pt_config: config::PageTableConfig) -> Self {
+        let policy = replacement::new_policy(
+            config.replacement, config.sets as usize, config.set_entries as usize);
+        CPUCache::with_policy(config, pt_config, policy)
+    }
+
+    /// Builds a cache around a replacement policy that can't be described by the config alone.
+    pub fn with_policy(
+        config: config::CacheConfig,
+        pt_config: config::PageTableConfig,
+        policy: Box<dyn ReplacementPolicy>,
+    ) -> Self {
+        let empty_set = vec![ None ; config.set_entries as usize ];
         let sets = vec![ empty_set ; config.sets as usize ];
-        CPUCache { sets, config, global_config, }
+        let prefetcher = config.prefetch.map(prefetch::new_prefetcher);
+        CPUCache { sets, policy, prefetcher, prefetches: PrefetchStats::default(), config, pt_config, }
+    }
+
+    pub fn config(&self) -> &config::CacheConfig {
+        &self.config
     }
 
     /// Performs a read access to the cache
-    pub fn read(&mut self, addr: u32) -> CacheResponse {
-        let (ppn, _page_offset) = bits::split_at(addr, self.global_config.pt.offset_size);
+    pub fn read(&mut self, addr: u64) -> CacheResponse {
+        let (ppn, _page_offset) = bits::split_at(addr, self.pt_config.offset_size);
         let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
         let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
 
-        let set = &mut self.sets[idx as usize];
-
-        let (result, writeback, eviction) = match set.lookup(tag) {
+        let (result, writeback, eviction) = match self.lookup(idx, tag) {
             // Some block found: Hit
-            Some(_block) => {
+            Some(way) => {
+                self.use_line(idx, way);
                 (QueryResult::Hit, None, None)
             },
             // No block found: Miss
@@ -62,8 +93,11 @@ impl CPUCache {
                     addr,
                     ppn,
                     dirty: false,
+                    prefetched: false,
+                    shared: false,
+                    forward: false,
                 };
-                let evicted_block = set.push(new_entry);
+                let evicted_block = self.insert(idx, block_addr, new_entry);
                 let evicted_addr = evicted_block.map(|b| b.addr);
 
                 let writeback = evicted_block
@@ -84,31 +118,114 @@ impl CPUCache {
         }
     }
 
-    pub fn write_force(&mut self, addr: u32) -> CacheResponse {
-        self.write_internal(addr, true)
+    pub fn write_force(&mut self, addr: u64) -> CacheResponse {
+        self.write_internal(addr, true, true)
     }
 
-    pub fn write(&mut self, addr: u32) -> CacheResponse {
-        self.write_internal(addr, false)
+    /// Performs a demand write. A miss fills the line only under write-allocate, and a
+    /// write-through level fills it clean, since the write goes on below as well.
+    pub fn write(&mut self, addr: u64) -> CacheResponse {
+        let allocate = self.config.write_miss_policy == WriteAllocate;
+        let response = self.write_internal(addr, allocate, self.config.write_policy == WriteBack);
+        self.use_addr(addr, response.result);
+        response
     }
 
-    /// Performs a write access to the cache according to the write policy.
-    fn write_internal(&mut self, addr: u32, force: bool) -> CacheResponse {
-        let (ppn, _page_offset) = bits::split_at(addr, self.global_config.pt.offset_size);
+    /// Performs a write that never allocates, whatever the write miss policy
+    pub fn write_around(&mut self, addr: u64) -> CacheResponse {
+        let response = self.write_internal(addr, false, false);
+        self.use_addr(addr, response.result);
+        response
+    }
+
+    /// Lets the prefetcher see a demand access, returning the addresses of the lines it wants
+    /// filled. Lines that are already here, or that are in a different page than the access
+    /// (whose physical location can't be known), are left out.
+    pub fn prefetch_candidates(&mut self, pc: Option<u64>, addr: u64, result: QueryResult) -> Vec<u64> {
+        let (block, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (ppn, _page_offset) = bits::split_at(addr, self.pt_config.offset_size);
+        let blocks = match self.prefetcher.as_mut() {
+            Some(prefetcher) => prefetcher.observe(pc, block, result == QueryResult::Hit),
+            None => return Vec::new(),
+        };
+        blocks.into_iter()
+            .map(|block| bits::join_at(block, 0, self.config.offset_size))
+            .filter(|&line_addr| bits::split_at(line_addr, self.pt_config.offset_size).0 == ppn)
+            .filter(|&line_addr| !self.holds(line_addr))
+            .collect()
+    }
+
+    /// Fills a line on behalf of the prefetcher. It counts as unused until a demand access hits it.
+    pub fn prefetch(&mut self, addr: u64) -> CacheResponse {
+        let (ppn, _page_offset) = bits::split_at(addr, self.pt_config.offset_size);
         let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
         let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
 
-        let set = &mut self.sets[idx as usize];
-        let (result, writeback, eviction) = match set.lookup(tag) {
+        self.prefetches.issued += 1;
+        let entry = CacheEntry { tag, addr, ppn, dirty: false, prefetched: true, shared: false, forward: false };
+        let evicted_block = self.insert(idx, block_addr, entry);
+        let writeback = evicted_block.filter(|b| b.is_dirty()).map(|b| b.addr);
+
+        CacheResponse { tag, idx, result: QueryResult::Miss, writeback, eviction: evicted_block.map(|b| b.addr) }
+    }
+
+    /// Whether a line holding `addr` is present (without touching it)
+    pub fn holds(&self, addr: u64) -> bool {
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+        self.sets[idx as usize].iter().any(|e| matches!(e, Some(e) if e.tag == tag))
+    }
+
+    /// Coherence state of the line holding `addr` (without touching it)
+    pub fn line_state(&self, addr: u64) -> LineState {
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+        match self.sets[idx as usize].iter().flatten().find(|e| e.tag == tag) {
+            Some(entry) => LineState::of(entry.dirty, entry.shared, entry.forward),
+            None => LineState::Invalid,
+        }
+    }
+
+    /// Moves the line holding `addr` (if there is one) into a coherence state. Leaving the
+    /// dirty states cleans the line, so its data has to have gone somewhere first.
+    pub fn set_state(&mut self, addr: u64, state: LineState) {
+        if state == LineState::Invalid {
+            self.clean_addr(addr);
+            return;
+        }
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+        if let Some(entry) = self.sets[idx as usize].iter_mut().flatten().find(|e| e.tag == tag) {
+            entry.dirty = matches!(state, LineState::Modified | LineState::Owned);
+            entry.shared = matches!(state, LineState::Owned | LineState::Shared | LineState::Forward);
+            entry.forward = state == LineState::Forward;
+        }
+    }
+
+    /// What the prefetcher has done so far, if there is one
+    pub fn prefetch_stats(&self) -> Option<PrefetchStats> {
+        self.prefetcher.as_ref().map(|_| self.prefetches)
+    }
+
+    /// Performs a write access to the cache according to the write policy. A miss fills the
+    /// line if `allocate`, dirty if `fill_dirty`.
+    fn write_internal(&mut self, addr: u64, allocate: bool, fill_dirty: bool) -> CacheResponse {
+        let (ppn, _page_offset) = bits::split_at(addr, self.pt_config.offset_size);
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+
+        let (result, writeback, eviction) = match self.lookup(idx, tag) {
             // Some block found: Hit
-            Some(block) => {
+            Some(way) => {
                 if self.config.write_policy == WriteBack {
-                    block.borrow_mut().enfilthen();
+                    if let Some(block) = self.sets[idx as usize][way].as_mut() {
+                        block.enfilthen();
+                    }
                 }
                 (QueryResult::Hit, None, None)
             },
             // No block found: Miss
-            None if self.config.write_miss_policy == NoWriteAllocate && !force => {
+            None if !allocate => {
                 (QueryResult::Miss, None, None)
             },
             None => {
@@ -116,9 +233,12 @@ impl CPUCache {
                     tag,
                     addr,
                     ppn,
-                    dirty: true,
+                    dirty: fill_dirty,
+                    prefetched: false,
+                    shared: false,
+                    forward: false,
                 };
-                let evicted_block = set.push(new_entry);
+                let evicted_block = self.insert(idx, block_addr, new_entry);
                 let evicted_addr = evicted_block.map(|b| b.addr);
 
                 let writeback = evicted_block
@@ -139,11 +259,66 @@ impl CPUCache {
         }
     }
 
+    /// Removes the line holding `addr`, for a level that is exclusive of the one above: a hit
+    /// hands the line (and whether it is dirty) up to the level above.
+    pub fn take(&mut self, addr: u64) -> (CacheResponse, Option<CacheEntry>) {
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+
+        let taken = self.lookup(idx, tag).and_then(|way| {
+            self.policy.invalidate(idx as usize, way);
+            self.sets[idx as usize][way].take()
+        });
+        let result = if taken.is_some() { QueryResult::Hit } else { QueryResult::Miss };
+
+        (CacheResponse { tag, idx, result, writeback: None, eviction: None }, taken)
+    }
+
+    /// Fills a line evicted from the level above, for a level that is exclusive of it. The line
+    /// keeps its dirty state.
+    pub fn fill_victim(&mut self, addr: u64, dirty: bool) -> CacheResponse {
+        let (ppn, _page_offset) = bits::split_at(addr, self.pt_config.offset_size);
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+
+        let (result, writeback, eviction) = match self.lookup(idx, tag) {
+            Some(way) => {
+                if let Some(block) = self.sets[idx as usize][way].as_mut() {
+                    block.dirty |= dirty;
+                }
+                (QueryResult::Hit, None, None)
+            },
+            None => {
+                let evicted_block = self.insert(idx, block_addr, CacheEntry { tag, addr, ppn, dirty, prefetched: false, shared: false, forward: false });
+                let writeback = evicted_block.filter(|b| b.is_dirty()).map(|b| b.addr);
+                (QueryResult::Miss, writeback, evicted_block.map(|b| b.addr))
+            },
+        };
+
+        CacheResponse { tag, idx, result, writeback, eviction }
+    }
+
+    /// Merges a writeback from the level above into the line holding it, if there is one.
+    /// Returns false if the line isn't present (so the writeback has to go further down).
+    pub fn absorb_writeback(&mut self, addr: u64) -> bool {
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+
+        let set = &mut self.sets[idx as usize];
+        match set.iter_mut().flatten().find(|e| e.tag == tag) {
+            Some(block) => {
+                block.enfilthen();
+                true
+            },
+            None => false,
+        }
+    }
+
     /// Invalidates all entries in teh cache that refer to the supplied PPN
-    pub fn clean_ppn(&mut self, ppn: u32) -> Option<Vec<u32>> {
-        let mut writebacks = Vec::<u32>::new();
-        for set in self.sets.iter_mut() {
-            if let Some(mut set_writebacks) = set.invalidate_entries_by_ppn(ppn) {
+    pub fn clean_ppn(&mut self, ppn: u64) -> Option<Vec<u64>> {
+        let mut writebacks = Vec::<u64>::new();
+        for idx in 0..self.sets.len() {
+            if let Some(mut set_writebacks) = self.invalidate_where(idx, |entry| entry.ppn == ppn) {
                 writebacks.append(&mut set_writebacks);
             }
         }
@@ -157,136 +332,93 @@ impl CPUCache {
         }
     }    
     
-    pub fn clean_addr(&mut self, addr: u32) -> Option<Vec<u32>> {
-        let (_ppn, _page_offset) = bits::split_at(addr, self.global_config.pt.offset_size);
+    /// Invalidates the line holding `addr`, returning it if there was one
+    pub fn clean_addr(&mut self, addr: u64) -> Option<CacheEntry> {
         let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
-        let (_tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+        let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
 
-        let set = &mut self.sets[idx as usize];
-        if let Some(writebacks) = set.invalidate_entries_by_addr(addr) {
-            Some(writebacks)
-        } else {
-            None
-        }
+        let set = idx as usize;
+        let way = self.sets[set].iter().position(|e| matches!(e, Some(e) if e.tag == tag))?;
+        self.policy.invalidate(set, way);
+        self.sets[set][way].take()
     }
-}
 
-/* === LRU Set === */
-
-use std::{collections::VecDeque, cell::RefCell, rc::Rc};
-#[derive(Clone, Debug)]
-/// A simple LRU Set which evicts elements upon insertion such that the set never exceeds `capacity`
-/// This one is tailored for use with the CPUCache. DO NOT GENERALIZE THIS AGAIN, PLEASE.
-//
-//  This could be faster. By using a VecDequeue, 'touching' an item of the cache is O(n). Ideally,
-//  some kind of linked hash map could be used for O(1) lookup _and_ O(1) touch. I suspect that
-//  designing that from scratch would have some serious rust shenanigans that I don't want to deal with.
-//  Also, the max set size is like, 16. Just a note for future reference.
-struct LRUSet {
-    inner: VecDeque<Rc<RefCell<CacheEntry>>>,
-    capacity: usize,
-}
+    /// Invalidates the line that was filled from exactly `addr` (not just from the same line),
+    /// returning it if there was one
+    pub fn clean_exact(&mut self, addr: u64) -> Option<CacheEntry> {
+        let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+        let (_tag, idx) = bits::split_at(block_addr, self.config.idx_size);
 
-impl LRUSet {
-    fn new(capacity: usize) -> Self {
-        let inner = VecDeque::<Rc<RefCell<CacheEntry>>>::with_capacity(capacity);
-        LRUSet { inner, capacity }
+        let set = idx as usize;
+        let way = self.sets[set].iter().position(|e| matches!(e, Some(e) if e.addr == addr))?;
+        self.policy.invalidate(set, way);
+        self.sets[set][way].take()
     }
 
-    /// Push an item to the LRU Set, potentially evicting the oldest item
-    fn push(&mut self, entry: CacheEntry) -> Option<CacheEntry> {
-        let evicted_item = if self.inner.len() >= self.capacity {
-            self.inner.pop_back()
-                .map(|entry| {
-                    entry.borrow().clone()
-                })
-        } else { None };
-        self.inner.push_front(Rc::new(RefCell::new(entry)));
-        evicted_item
+    /// Set dueling results from the replacement policy, if it duels.
+    pub fn duel_stats(&self) -> Option<DuelStats> {
+        self.policy.duel_stats()
     }
 
-    /// Look up an item in the LRU Set. If found, the item is 'touched' and moved to the front
-    /// of the queue.
-    fn lookup(&mut self, tag: u32) -> Option<Rc<RefCell<CacheEntry>>> {
-        let item_search = self.inner
-            .iter()
-            .position(|entry| entry.borrow().tag == tag );
-        
-        if let Some(item_idx) = item_search {
-            let item = self.inner.remove(item_idx).unwrap();
-            self.inner.push_front(item.clone());
-            Some(item)
-        } else {
-            None
+    /// A demand access used the line in the way, so it no longer counts as an unused prefetch.
+    fn use_line(&mut self, idx: u64, way: usize) {
+        if let Some(block) = self.sets[idx as usize][way].as_mut().filter(|b| b.prefetched) {
+            block.prefetched = false;
+            self.prefetches.useful += 1;
         }
     }
 
-    /// Evicts any entry that corresponds to the supplied ppn. Returns a list of writebacks
-    fn invalidate_entries_by_ppn(&mut self, ppn: u32) -> Option<Vec<u32>> {
-        let mut writebacks = Vec::new();
+    /// `use_line` for a demand write that has already been performed
+    fn use_addr(&mut self, addr: u64, result: QueryResult) {
+        if result == QueryResult::Hit {
+            let (block_addr, _block_offset) = bits::split_at(addr, self.config.offset_size);
+            let (tag, idx) = bits::split_at(block_addr, self.config.idx_size);
+            if let Some(way) = self.sets[idx as usize].iter().position(|e| matches!(e, Some(e) if e.tag == tag)) {
+                self.use_line(idx, way);
+            }
+        }
+    }
 
-        // Copy the entire LRU set without the invalid entries
-        let new_inner = self.inner
+    /// Look up a tag in a set. If found, the line is 'touched' in the eyes of the replacement
+    /// policy and its way is returned.
+    fn lookup(&mut self, idx: u64, tag: u64) -> Option<usize> {
+        let way = self.sets[idx as usize]
             .iter()
-            // filter out invalid entries and push them to writebacks if dirty
-            .filter_map(|entry| {
-                if entry.borrow().ppn == ppn {
-                    if entry.borrow().is_dirty() {
-                        writebacks.push(entry.borrow().addr);
-                    }
-                    None
-                } else {
-                    Some(entry.borrow().clone())
-                }
-            })
-            // take raw entries and box them up for shipping
-            .map(|raw_entry| {
-                Rc::new(RefCell::new(raw_entry))
-            })
-            .collect();
-        
-        // Set the LRUSet's inner to be the filtered set
-        // -- This drops the old inner completely.
-        self.inner = new_inner;
-
-        // FIXING THE CODE FOR SHIVAM: rustc 1.58 does not support this
-        //(!writebacks.is_empty()).then_some(writebacks)
+            .position(|entry| matches!(entry, Some(e) if e.tag == tag))?;
+        self.policy.touch(idx as usize, way);
+        Some(way)
+    }
 
-        // Return the writebacks
-        if !writebacks.is_empty() {
-            Some(writebacks)
-        } else {
-            None
+    /// Places a new line in a set, evicting whichever line the replacement policy picks if the
+    /// set is full. Empty ways are always used first.
+    fn insert(&mut self, idx: u64, block_addr: u64, entry: CacheEntry) -> Option<CacheEntry> {
+        let set = idx as usize;
+        let way = match self.sets[set].iter().position(|e| e.is_none()) {
+            Some(way) => way,
+            None => self.policy.victim(set),
+        };
+        let evicted = self.sets[set][way].replace(entry);
+        if matches!(evicted, Some(e) if e.prefetched) {
+            self.prefetches.unused_evicted += 1;
         }
+        self.policy.place(set, way, block_addr);
+        self.policy.fill(set, way);
+        evicted
     }
 
-    /// Evicts any entry that corresponds to the supplied ppn. Returns a list of writebacks
-    fn invalidate_entries_by_addr(&mut self, addr: u32) -> Option<Vec<u32>> {
+    /// Evicts any entry in the set matching the predicate. Returns a list of writebacks
+    fn invalidate_where(&mut self, set: usize, pred: impl Fn(&CacheEntry) -> bool) -> Option<Vec<u64>> {
         let mut writebacks = Vec::new();
 
-        // Copy the entire LRU set without the invalid entries
-        let new_inner = self.inner
-            .iter()
-            // filter out invalid entries and push them to writebacks if dirty
-            .filter_map(|entry| {
-                if entry.borrow().addr == addr {
-                    if entry.borrow().is_dirty() {
-                        writebacks.push(entry.borrow().addr);
-                    }
-                    None
-                } else {
-                    Some(entry.borrow().clone())
+        for way in 0..self.sets[set].len() {
+            if let Some(entry) = self.sets[set][way].filter(|e| pred(e)) {
+                if entry.is_dirty() {
+                    writebacks.push(entry.addr);
                 }
-            })
-            // take raw entries and box them up for shipping
-            .map(|raw_entry| {
-                Rc::new(RefCell::new(raw_entry))
-            })
-            .collect();
-        
-        // Set the LRUSet's inner to be the filtered set
-        // -- This drops the old inner completely.
-        self.inner = new_inner;
+                self.sets[set][way] = None;
+                self.policy.invalidate(set, way);
+            }
+        }
 
         // FIXING THE CODE FOR SHIVAM: rustc 1.58 does not support this
         //(!writebacks.is_empty()).then_some(writebacks)
@@ -302,15 +434,14 @@ impl LRUSet {
 
 impl std::fmt::Debug for CPUCache {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "L{} Cache:", self.config.id)?;
+        writeln!(f, "L{} Cache ({}):", self.config.id, self.config.replacement.as_str())?;
         for (idx, set) in self.sets.iter().enumerate() {
             writeln!(f, "\tSet {:x}:", idx)?;
-            for entry in set.inner.iter() {
-                let e = entry.borrow();
+            for e in set.iter().flatten() {
                 writeln!(f, "\t\taddr: {:x}\n\t\ttag: {:x}\n\t\tppn: {:x}\n\t\tdirty: {}",
                     e.addr, e.tag, e.ppn, if e.is_dirty() { "yes" } else { "no" })?;
             }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use crate::config::CoherenceProtocol::{self, *};
+
+/// Coherence state of a line in one core's data cache. MESI uses the first four; MOESI adds
+/// `Owned` and MESIF adds `Forward`.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum LineState {
+    /// The only copy, and dirty
+    Modified,
+    /// Dirty, but other cores may have (clean) copies too. This copy answers for the line.
+    Owned,
+    /// The only copy, and clean
+    Exclusive,
+    /// Other cores may have a copy too, and it is clean
+    Shared,
+    /// Shared and clean, and the copy that answers another core's miss
+    Forward,
+    /// Not in the cache
+    Invalid,
+}
+
+impl LineState {
+    /// The state of a line that is present, from its dirty bit, whether another core may share
+    /// it, and whether it is the shared copy that forwards.
+    pub fn of(dirty: bool, shared: bool, forward: bool) -> Self {
+        match (dirty, shared, forward) {
+            (true, true, _) => Self::Owned,
+            (true, false, _) => Self::Modified,
+            (false, false, _) => Self::Exclusive,
+            (false, true, true) => Self::Forward,
+            (false, true, false) => Self::Shared,
+        }
+    }
+
+    /// Whether this copy is the one that answers for the line
+    pub fn is_owner(&self) -> bool {
+        matches!(self, Self::Modified | Self::Owned | Self::Exclusive | Self::Forward)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Modified => "M",
+            Self::Owned => "O",
+            Self::Exclusive => "E",
+            Self::Shared => "S",
+            Self::Forward => "F",
+            Self::Invalid => "I",
+        }
+    }
+}
+
+/// What another core's copy of a line does when a core asks for it.
+#[derive(Copy, Clone, Debug)]
+pub struct PeerAction {
+    /// The copy's state afterwards
+    pub next: LineState,
+    /// Its data goes straight to the asking core's DC, which doesn't have to look below
+    pub supplies: bool,
+    /// Its dirty data is written back below the DCs
+    pub writes_back: bool,
+}
+
+/// A copy in `state` sees another core miss on a read. Only MOESI's and MESIF's owners hand
+/// their data over; MESI's dirty copy is written back for the miss to find below.
+pub fn remote_read(protocol: CoherenceProtocol, state: LineState) -> PeerAction {
+    use LineState::*;
+    let (next, supplies, writes_back) = match (protocol, state) {
+        (_, Invalid) => (Invalid, false, false),
+        (Mesi, Modified) => (Shared, false, true),
+        (Moesi, Modified) | (Moesi, Owned) => (Owned, true, false),
+        (Mesif, Modified) => (Shared, true, true),
+        (Mesif, Exclusive) | (Mesif, Forward) => (Shared, true, false),
+        (_, _) => (Shared, false, false),
+    };
+    PeerAction { next, supplies, writes_back }
+}
+
+/// A copy in `state` sees another core write the line, which invalidates it. On an `upgrade`
+/// the writer already has the data (and takes over a dirty copy's obligation to write it back).
+pub fn remote_write(protocol: CoherenceProtocol, state: LineState, upgrade: bool) -> PeerAction {
+    use LineState::*;
+    let dirty = matches!(state, Modified | Owned);
+    let (supplies, writes_back) = match (protocol, state) {
+        (_, Invalid) => (false, false),
+        _ if upgrade => (false, false),
+        (Mesi, _) => (false, dirty),
+        (Moesi, _) => (dirty, false),
+        (Mesif, _) => (state.is_owner(), false),
+    };
+    PeerAction { next: Invalid, supplies, writes_back }
+}
+
+/// The state a read miss fills its line in, given whether other cores still have copies. In
+/// MESIF the newest copy takes over forwarding.
+pub fn read_fill(protocol: CoherenceProtocol, shared: bool) -> LineState {
+    match (protocol, shared) {
+        (_, false) => LineState::Exclusive,
+        (Mesif, true) => LineState::Forward,
+        (_, true) => LineState::Shared,
+    }
+}
+
+/// What the directory knows about one line.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct DirectoryEntry {
+    /// A bit for each core that may have a copy
+    pub sharers: u32,
+    /// The core whose copy answers for the line, if one does
+    pub owner: Option<usize>,
+}
+
+/// A full-map directory beside the shared cache, standing in for snooping: it knows which cores
+/// to contact for a line instead of asking all of them. Clean lines leave a DC without telling
+/// it, so a sharer bit (or the owner) may outlive its copy.
+#[derive(Default, Debug)]
+pub struct Directory {
+    entries: HashMap<u64, DirectoryEntry>,
+}
+
+impl Directory {
+    /// What the directory has on a block (nothing, if no DC has asked for it).
+    pub fn lookup(&self, block: u64) -> DirectoryEntry {
+        self.entries.get(&block).copied().unwrap_or_default()
+    }
+
+    /// Records the copies of a block once a request for it is done. A write leaves only the
+    /// copies listed; a read adds them to those already known.
+    pub fn update(&mut self, block: u64, holders: u32, owner: Option<usize>, write: bool) {
+        let entry = self.entries.entry(block).or_default();
+        entry.sharers = if write { holders } else { entry.sharers | holders };
+        entry.owner = owner;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LineState::*;
+
+    fn action(action: PeerAction) -> (LineState, bool, bool) {
+        (action.next, action.supplies, action.writes_back)
+    }
+
+    #[test]
+    fn remote_read_table() {
+        let table = [
+            (Mesi, Modified, (Shared, false, true)),
+            (Mesi, Exclusive, (Shared, false, false)),
+            (Mesi, Shared, (Shared, false, false)),
+            (Mesi, Invalid, (Invalid, false, false)),
+            (Moesi, Modified, (Owned, true, false)),
+            (Moesi, Owned, (Owned, true, false)),
+            (Moesi, Exclusive, (Shared, false, false)),
+            (Moesi, Shared, (Shared, false, false)),
+            (Moesi, Invalid, (Invalid, false, false)),
+            (Mesif, Modified, (Shared, true, true)),
+            (Mesif, Exclusive, (Shared, true, false)),
+            (Mesif, Forward, (Shared, true, false)),
+            (Mesif, Shared, (Shared, false, false)),
+            (Mesif, Invalid, (Invalid, false, false)),
+        ];
+        for (protocol, state, expected) in table {
+            assert_eq!(action(remote_read(protocol, state)), expected, "{:?} {:?}", protocol, state);
+        }
+    }
+
+    #[test]
+    fn remote_write_table() {
+        let table = [
+            (Mesi, Modified, (false, true)),
+            (Mesi, Exclusive, (false, false)),
+            (Mesi, Shared, (false, false)),
+            (Moesi, Modified, (true, false)),
+            (Moesi, Owned, (true, false)),
+            (Moesi, Exclusive, (false, false)),
+            (Moesi, Shared, (false, false)),
+            (Mesif, Modified, (true, false)),
+            (Mesif, Exclusive, (true, false)),
+            (Mesif, Forward, (true, false)),
+            (Mesif, Shared, (false, false)),
+        ];
+        for (protocol, state, (supplies, writes_back)) in table {
+            let expected = (Invalid, supplies, writes_back);
+            assert_eq!(action(remote_write(protocol, state, false)), expected, "{:?} {:?}", protocol, state);
+            // An upgrading writer already has the data
+            assert_eq!(action(remote_write(protocol, state, true)), (Invalid, false, false), "{:?} {:?}", protocol, state);
+        }
+        for protocol in [Mesi, Moesi, Mesif] {
+            assert_eq!(action(remote_write(protocol, Invalid, false)), (Invalid, false, false));
+        }
+    }
+
+    #[test]
+    fn read_fill_forwards_only_in_mesif() {
+        assert_eq!(read_fill(Mesi, true), Shared);
+        assert_eq!(read_fill(Moesi, true), Shared);
+        assert_eq!(read_fill(Mesif, true), Forward);
+        assert_eq!(read_fill(Mesif, false), Exclusive);
+    }
+
+    #[test]
+    fn directory_reads_add_sharers_and_writes_replace_them() {
+        let mut directory = Directory::default();
+        directory.update(7, 0b01, Some(0), false);
+        directory.update(7, 0b10, None, false);
+        assert_eq!(directory.lookup(7).sharers, 0b11);
+        directory.update(7, 0b100, Some(2), true);
+        assert_eq!(directory.lookup(7).sharers, 0b100);
+        assert_eq!(directory.lookup(7).owner, Some(2));
+        assert_eq!(directory.lookup(8).sharers, 0);
+    }
+}
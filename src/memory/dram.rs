@@ -0,0 +1,175 @@
+use crate::config::{AddressMapping, DramConfig, RowBufferPolicy};
+
+/// Which bank and row a physical address lives in (its column doesn't matter to the timing).
+#[derive(Copy, Clone, Debug)]
+pub struct DramLocation {
+    pub channel: u64,
+    pub rank: u64,
+    pub bank: u64,
+    pub row: u64,
+}
+
+/// What a request found in its bank's row buffer.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum RowResult {
+    /// Its row was already open
+    Hit,
+    /// No row was open, so its row had to be opened
+    Empty,
+    /// Another row was open, so it had to be closed before this one was opened
+    Conflict,
+}
+
+pub struct DramResponse {
+    /// Index of the bank, counting every bank of every rank of every channel
+    pub bank: usize,
+    pub result: RowResult,
+    pub cycles: u64,
+}
+
+/// Banks of DRAM behind the caches, each remembering the row in its row buffer.
+#[derive(Debug)]
+pub struct Dram {
+    /// The open row of each bank, by `DramResponse::bank`
+    open_rows: Vec<Option<u64>>,
+    config: DramConfig,
+}
+
+impl Dram {
+    pub fn new(config: DramConfig) -> Self {
+        let banks = (config.channels * config.ranks * config.banks) as usize;
+        Dram { open_rows: vec![None; banks], config }
+    }
+
+    /// Splits a physical address into its DRAM coordinates under the configured mapping.
+    pub fn locate(&self, addr: u64) -> DramLocation {
+        let c = &self.config;
+        let mut rest = addr;
+        let mut take = |n: u32| {
+            let field = rest & ((1 << n) - 1);
+            rest >>= n;
+            field
+        };
+        let (channel_bits, rank_bits, bank_bits) =
+            (c.channels.trailing_zeros(), c.ranks.trailing_zeros(), c.banks.trailing_zeros());
+        let column_bits = c.row_size.trailing_zeros();
+
+        let mut location = match c.mapping {
+            AddressMapping::RowBankColumn | AddressMapping::Permutation => {
+                take(column_bits);
+                let channel = take(channel_bits);
+                let bank = take(bank_bits);
+                let rank = take(rank_bits);
+                DramLocation { channel, rank, bank, row: rest }
+            },
+            AddressMapping::RowColumnBank => {
+                take(c.line_offset);
+                let channel = take(channel_bits);
+                let bank = take(bank_bits);
+                let rank = take(rank_bits);
+                take(column_bits - c.line_offset);
+                DramLocation { channel, rank, bank, row: rest }
+            },
+        };
+        if c.mapping == AddressMapping::Permutation {
+            location.bank ^= location.row & (c.banks as u64 - 1);
+        }
+        location
+    }
+
+    /// Serves a request for the line at `addr`, opening (and maybe first closing) a row if it
+    /// isn't the open one.
+    pub fn access(&mut self, addr: u64) -> DramResponse {
+        let c = self.config;
+        let location = self.locate(addr);
+        let bank = (((location.channel * c.ranks as u64) + location.rank) * c.banks as u64 + location.bank) as usize;
+
+        let (result, cycles) = match self.open_rows[bank] {
+            Some(row) if row == location.row => (RowResult::Hit, c.t_cas),
+            Some(_) => (RowResult::Conflict, c.t_rp + c.t_rcd + c.t_cas),
+            None => (RowResult::Empty, c.t_rcd + c.t_cas),
+        };
+        self.open_rows[bank] = match c.policy {
+            RowBufferPolicy::Open => Some(location.row),
+            RowBufferPolicy::Closed => None,
+        };
+        DramResponse { bank, result, cycles }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2 channels of 2 ranks of 4 banks, with 1KB rows and 64B lines
+    fn dram(mapping: AddressMapping, policy: RowBufferPolicy) -> Dram {
+        Dram::new(DramConfig {
+            channels: 2,
+            ranks: 2,
+            banks: 4,
+            row_size: 1024,
+            mapping,
+            policy,
+            line_offset: 6,
+            t_cas: 10,
+            t_rcd: 20,
+            t_rp: 30,
+        })
+    }
+
+    fn coordinates(location: DramLocation) -> (u64, u64, u64, u64) {
+        (location.channel, location.rank, location.bank, location.row)
+    }
+
+    #[test]
+    fn row_bank_column_keeps_a_row_in_one_bank() {
+        let dram = dram(AddressMapping::RowBankColumn, RowBufferPolicy::Open);
+        let addr = (5 << 14) | (1 << 13) | (3 << 11) | (1 << 10) | 0x123;
+        assert_eq!(coordinates(dram.locate(addr)), (1, 1, 3, 5));
+        assert_eq!(coordinates(dram.locate(addr + 64)), (1, 1, 3, 5));
+    }
+
+    #[test]
+    fn row_column_bank_spreads_consecutive_lines() {
+        let dram = dram(AddressMapping::RowColumnBank, RowBufferPolicy::Open);
+        let addr = (5 << 14) | (0xa << 10) | (1 << 9) | (2 << 7) | (1 << 6) | 0x21;
+        assert_eq!(coordinates(dram.locate(addr)), (1, 1, 2, 5));
+        assert_eq!(coordinates(dram.locate(0)), (0, 0, 0, 0));
+        assert_eq!(coordinates(dram.locate(64)), (1, 0, 0, 0));
+        assert_eq!(coordinates(dram.locate(128)), (0, 0, 1, 0));
+    }
+
+    #[test]
+    fn permutation_xors_the_bank_with_the_row() {
+        let dram = dram(AddressMapping::Permutation, RowBufferPolicy::Open);
+        let addr = (5 << 14) | (1 << 13) | (3 << 11) | (1 << 10) | 0x123;
+        assert_eq!(coordinates(dram.locate(addr)), (1, 1, 3 ^ 1, 5));
+        // The same bank bits in rows 0 to 3 land in four different banks
+        let banks: Vec<u64> = (0..4).map(|row| dram.locate((row << 14) | (2 << 11)).bank).collect();
+        assert_eq!(banks, vec![2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn open_rows_hit_and_conflict() {
+        let mut dram = dram(AddressMapping::RowBankColumn, RowBufferPolicy::Open);
+        let other_row = 1 << 14;
+        let results: Vec<(RowResult, u64)> = [0, 64, other_row, other_row + 64]
+            .iter()
+            .map(|&addr| dram.access(addr))
+            .map(|response| (response.result, response.cycles))
+            .collect();
+        assert_eq!(results, vec![
+            (RowResult::Empty, 30),
+            (RowResult::Hit, 10),
+            (RowResult::Conflict, 60),
+            (RowResult::Hit, 10),
+        ]);
+    }
+
+    #[test]
+    fn closed_rows_are_always_empty() {
+        let mut dram = dram(AddressMapping::RowBankColumn, RowBufferPolicy::Closed);
+        assert_eq!(dram.access(0).result, RowResult::Empty);
+        assert_eq!(dram.access(64).result, RowResult::Empty);
+    }
+}
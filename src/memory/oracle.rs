@@ -0,0 +1,67 @@
+use std::{cell::Cell, collections::HashMap, rc::Rc};
+
+use crate::{config::Config, utils::bits};
+
+/// Every position in the trace at which each block (or page) is referenced, in order.
+#[derive(Default, Debug)]
+pub struct FutureUses {
+    uses: HashMap<u64, Vec<usize>>,
+}
+
+impl FutureUses {
+    fn record(&mut self, key: u64, pos: usize) {
+        self.uses.entry(key).or_default().push(pos);
+    }
+
+    /// The first position after `now` at which `key` is referenced again, or `usize::MAX` if it
+    /// never is.
+    pub fn next_use(&self, key: u64, now: usize) -> usize {
+        self.uses.get(&key)
+            .and_then(|uses| uses.get(uses.partition_point(|&pos| pos <= now)))
+            .copied()
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// Future knowledge for Belady's OPT, built from an earlier pass over the whole trace.
+///
+/// Replacement policy doesn't change where anything lives in physical memory (the page table
+/// is touched the same way whether or not the TLB hits), so the physical addresses from a
+/// normal run are exactly the ones an OPT run will see.
+#[derive(Clone, Debug)]
+pub struct Oracle {
+    pub tlb: Rc<FutureUses>,
+    /// One per configured cache level
+    pub caches: Vec<Rc<FutureUses>>,
+    /// Position in the trace of the access currently being simulated
+    pub now: Rc<Cell<usize>>,
+}
+
+impl Oracle {
+    /// Builds the oracle from the `(translation key, physical address)` of every access in the
+    /// trace. The key is the first vpn of the access's page, with the ASID mixed in (see
+    /// `tlb::translation_key`).
+    pub fn new(config: &Config, refs: impl Iterator<Item = (Option<u64>, u64)>) -> Self {
+        let mut tlb = FutureUses::default();
+        let mut caches: Vec<FutureUses> = config.caches.iter().map(|_| Default::default()).collect();
+        for (pos, (key, physical_addr)) in refs.enumerate() {
+            if let Some(key) = key {
+                tlb.record(key, pos);
+            }
+            for (cache, uses) in config.caches.iter().zip(caches.iter_mut()) {
+                let (block, _) = bits::split_at(physical_addr, cache.offset_size);
+                uses.record(block, pos);
+            }
+        }
+        Oracle {
+            tlb: Rc::new(tlb),
+            caches: caches.into_iter().map(Rc::new).collect(),
+            now: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Moves on to the next access in the trace.
+    pub fn tick(&self) {
+        self.now.set(self.now.get() + 1);
+    }
+}
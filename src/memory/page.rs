@@ -1,67 +1,667 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    error::Error,
+    rc::Rc,
+};
+
 #[allow(unused_imports)]
 use crate::{
-    config,
-    utils::bits,
+    config::{self, FrameAllocation, PageReplacement, PTE_SIZE},
+    utils::{bits, rng::XorShift64},
     memory::QueryResult,
 };
 
 pub struct PageTableResponse {
-    pub vpn: u32,
-    pub ppn: u32,
-    pub page_offset: u32,
+    pub vpn: u64,
+    pub ppn: u64,
+    pub page_offset: u64,
     pub res: QueryResult,
-    pub evicted_ppn: Option<u32>,
+    /// Base page frames whose contents left them: the frames of an evicted page, or of the
+    /// pages merged into a promoted huge page
+    pub evicted_ppns: Vec<u64>,
+    /// Dirty pages written out to disk to make room (or cleaned ahead of time by WSClock)
+    pub disk_writes: u32,
+    /// Pages read in from disk: one for a major fault, none for a hit or a minor fault
+    pub disk_reads: u32,
+    /// log2 of the base pages in the page that translated the address (0 for a base page)
+    pub page_shift: u32,
+    /// The fault promoted the address's region to a huge page
+    pub promoted: bool,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct PageTableEntry {
-    vpn: u32,
-    ppn: u32,
+    /// Process whose page this is
+    pid: u32,
+    vpn: u64,
+    ppn: u64,
+    referenced: bool,
+    dirty: bool,
+    loaded_at: u64,
+    last_use: u64,
+    age: u32,
+}
+
+/// One PTE read of a radix page table walk.
+#[derive(Copy, Clone, Debug)]
+pub struct WalkStep {
+    pub pte_addr: u64,
+    /// The vpn bits that select this PTE: the indices of this level and every level above it
+    pub vpn_prefix: u64,
+}
+
+/// The tables of a hierarchical page table. Tables are laid out one after another from the
+/// configured base address the first time a walk needs them, and never freed.
+#[derive(Debug)]
+struct RadixTables {
+    config: config::RadixConfig,
+    /// Physical address of each table, by process, level and the vpn bits above that level
+    tables: HashMap<(u32, usize, u64), u64>,
+    next_addr: u64,
+}
+
+impl RadixTables {
+    fn new(config: config::RadixConfig) -> Self {
+        RadixTables { config, tables: HashMap::new(), next_addr: config.base_addr }
+    }
+
+    /// The PTE read at each level of a walk for `vpn` in process `pid`, root first. The walk
+    /// for a huge page `1 << leaf_shift` base pages long ends at the level whose entries cover
+    /// that much. Each process has its own tables.
+    fn walk(&mut self, pid: u32, vpn: u64, leaf_shift: u32) -> Vec<WalkStep> {
+        let levels = self.config.levels as usize;
+        let mut below: u32 = self.config.bits[..levels].iter().sum();
+        let mut ptes = Vec::with_capacity(levels);
+        for level in 0..levels {
+            let bits = self.config.bits[level];
+            below -= bits;
+            let prefix = vpn.checked_shr(below + bits).unwrap_or(0);
+            let index = (vpn >> below) & ((1 << bits) - 1);
+            let pte_addr = self.table(pid, level, prefix) + index * PTE_SIZE;
+            ptes.push(WalkStep { pte_addr, vpn_prefix: vpn >> below });
+            if below <= leaf_shift {
+                break;
+            }
+        }
+        ptes
+    }
+
+    /// The address of a table, allocating it (aligned to its size) if this is its first use
+    fn table(&mut self, pid: u32, level: usize, prefix: u64) -> u64 {
+        let size = (1 << self.config.bits[level]) * PTE_SIZE;
+        let next_addr = &mut self.next_addr;
+        *self.tables.entry((pid, level, prefix)).or_insert_with(|| {
+            let addr = (*next_addr + size - 1) & !(size - 1);
+            *next_addr = addr + size;
+            addr
+        })
+    }
+}
+
+/// Swap space on disk, which a page table shares with its huge page pools.
+#[derive(Debug)]
+struct Swap {
+    /// This table's pages (by process and vpn) with a copy in swap
+    swapped: HashSet<(u32, u64)>,
+    /// Base pages of swap each of this table's pages takes up
+    slot_size: u64,
+    /// Base pages of swap in use, by every table
+    used: Rc<Cell<u64>>,
+    /// Base pages of swap there are
+    capacity: u64,
+}
+
+impl Swap {
+    /// Gives a page being written out a slot, unless it already has one. Fails when swap is full.
+    fn write_out(&mut self, pid: u32, vpn: u64) -> Result<(), Box<dyn Error>> {
+        if self.swapped.contains(&(pid, vpn)) {
+            return Ok(());
+        }
+        if self.used.get() + self.slot_size > self.capacity {
+            error!("swap space is exhausted (all {} pages are in use)", self.capacity);
+        }
+        self.used.set(self.used.get() + self.slot_size);
+        self.swapped.insert((pid, vpn));
+        Ok(())
+    }
+}
+
+/// Hands out the frames that have never held a page, in the order the frame allocation policy
+/// picks. A frame's color is the cache sets its lines map to: frame `ppn` has color
+/// `ppn % colors`.
+#[derive(Debug)]
+struct FrameAllocator {
+    policy: FrameAllocation,
+    frames: u64,
+    colors: u64,
+    /// Frames handed out so far
+    allocated: u64,
+    /// Frames handed out so far of each color, for the colored policies
+    by_color: HashMap<u64, u64>,
+    /// Color of the last frame handed out by color
+    last_color: u64,
+    rng: XorShift64,
+    /// Positions a lazy Fisher-Yates shuffle of the frames has swapped, for random allocation
+    shuffled: HashMap<u64, u64>,
+}
+
+impl FrameAllocator {
+    fn new(policy: FrameAllocation, frames: u64, colors: u64) -> Self {
+        let seed = match policy {
+            FrameAllocation::Random { seed } => seed,
+            _ => 1,
+        };
+        FrameAllocator {
+            policy, frames, colors, allocated: 0, by_color: HashMap::new(), last_color: colors - 1,
+            rng: XorShift64::new(seed), shuffled: HashMap::new(),
+        }
+    }
+
+    /// The color page coloring gives process `pid`'s page `vpn`: the virtual page's own color,
+    /// or one of the process's share of the colors when they are partitioned.
+    fn wanted_color(&self, pid: u32, vpn: u64) -> Option<u64> {
+        match self.policy {
+            FrameAllocation::PageColoring { colors_per_process: None } => Some(vpn % self.colors),
+            FrameAllocation::PageColoring { colors_per_process: Some(n) } => {
+                let n = n.min(self.colors);
+                Some((pid as u64 * n + vpn % n) % self.colors)
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether frame `ppn` has `color` (any frame will do without one)
+    fn fits(&self, ppn: u64, color: Option<u64>) -> bool {
+        color.map_or(true, |color| ppn % self.colors == color)
+    }
+
+    /// Whether any frame has `color`
+    fn has_frames(&self, color: u64) -> bool {
+        color < self.frames
+    }
+
+    /// Picks a frame that has never been used. Only called while there are some left.
+    fn allocate(&mut self, color: Option<u64>) -> u64 {
+        let ppn = match self.policy {
+            FrameAllocation::Sequential => self.allocated,
+            FrameAllocation::Random { .. } => {
+                let i = self.allocated + self.rng.below(self.frames - self.allocated);
+                let at = |pos: u64| self.shuffled.get(&pos).copied().unwrap_or(pos);
+                let (picked, first) = (at(i), at(self.allocated));
+                self.shuffled.insert(i, first);
+                // Positions below the allocated count are never looked at again
+                self.shuffled.remove(&self.allocated);
+                picked
+            },
+            FrameAllocation::BinHopping => self.next_of_color((self.last_color + 1) % self.colors),
+            FrameAllocation::PageColoring { .. } => self.next_of_color(color.unwrap_or(0)),
+        };
+        self.allocated += 1;
+        ppn
+    }
+
+    /// The lowest unused frame of `color`, or of the first color after it that has one left
+    fn next_of_color(&mut self, color: u64) -> u64 {
+        let colors = self.colors;
+        let color = (0..colors).map(|i| (color + i) % colors)
+            .find(|c| c + self.by_color.get(c).copied().unwrap_or(0) * colors < self.frames)
+            .expect("only called while there are frames left");
+        let used = self.by_color.entry(color).or_default();
+        let ppn = color + *used * colors;
+        *used += 1;
+        self.last_color = color;
+        ppn
+    }
+}
+
+/// The frames set aside for one huge page size, which are managed as a page table of their own
+/// with the huge page as its page size.
+#[derive(Debug)]
+struct HugePool {
+    table: PageTable,
+    /// log2 of the base pages in one huge page
+    shift: u32,
+    /// Base frame the pool starts at
+    base_ppn: u64,
 }
 
 pub struct PageTable {
-    entries: LRUTable,
+    /// Resident pages of every process, in the order their frames were first handed out. The
+    /// processes compete for the frames.
+    frames: Vec<PageTableEntry>,
+    /// vpn -> index into `frames` for every resident page, by process
+    mappings: HashMap<u32, HashMap<u64, usize>>,
+    /// Process whose addresses are being translated
+    pid: u32,
+    /// Clock hand for the clock-style algorithms
+    hand: usize,
+    /// Number of references so far, which is the 'virtual time' of the process
+    clock: u64,
+    /// References since the aging counters were last shifted
+    since_aging: u32,
+    /// Disk writes made while servicing the current reference
+    disk_writes: u32,
+    /// Disk reads made while servicing the current reference
+    disk_reads: u32,
+    /// Where dirty pages go when they are evicted, if swap is modeled. Without it, every fault
+    /// reads the page from disk.
+    swap: Option<Swap>,
+    /// Frames (as indices into `frames`) given up by pages that were merged into a huge page, to
+    /// be used before evicting
+    free: Vec<usize>,
+    allocator: FrameAllocator,
+    radix: Option<RadixTables>,
+    /// One per huge page size, smallest first
+    huge: Vec<HugePool>,
     config: config::PageTableConfig,
 }
 
 impl PageTable {
     pub fn new(config: config::PageTableConfig) -> Self {
-        let entries = LRUTable::new(config.physical_pages as usize);
-        PageTable { entries, config, }
+        PageTable::sharing_swap(config, 0, Rc::new(Cell::new(0)))
+    }
+
+    /// Builds a page table whose pages are `1 << shift` base pages long, which uses the swap
+    /// space counted by `swap_used` along with the other tables.
+    fn sharing_swap(config: config::PageTableConfig, shift: u32, swap_used: Rc<Cell<u64>>) -> Self {
+        let huge = config.huge.map_or_else(Vec::new, |huge| {
+            huge.sizes.iter().flatten().enumerate().map(|(i, size)| HugePool {
+                table: PageTable::sharing_swap(config::PageTableConfig {
+                    virtual_pages: config.virtual_pages >> size.shift,
+                    physical_pages: size.frames as u64,
+                    page_size: size.size,
+                    idx_size: config.idx_size.saturating_sub(size.shift),
+                    offset_size: config.offset_size + size.shift,
+                    radix: None,
+                    huge: None,
+                    colors: (config.colors >> size.shift).max(1),
+                    ..config
+                }, size.shift, swap_used.clone()),
+                shift: size.shift,
+                base_ppn: huge.pool_start(i),
+            }).collect()
+        });
+        // Only the frames that aren't set aside for huge pages hold base pages
+        let config = config::PageTableConfig {
+            physical_pages: config.huge.map_or(config.physical_pages, |huge| huge.base_frames),
+            ..config
+        };
+
+        let frames = Vec::with_capacity(config.physical_pages as usize);
+        let allocator = FrameAllocator::new(config.frame_allocation, config.physical_pages, config.colors);
+        let mappings = HashMap::from([(0, HashMap::new())]);
+        let radix = config.radix.map(RadixTables::new);
+        let swap = config.swap_pages.map(|capacity| Swap {
+            swapped: HashSet::new(),
+            slot_size: 1 << shift,
+            used: swap_used,
+            capacity,
+        });
+        PageTable {
+            frames, mappings, pid: 0, hand: 0, clock: 0, since_aging: 0, disk_writes: 0, disk_reads: 0, swap,
+            free: Vec::new(), allocator, radix, huge, config,
+        }
+    }
+
+    /// Base pages of swap in use, if swap is modeled.
+    pub fn swap_used(&self) -> Option<u64> {
+        self.swap.as_ref().map(|swap| swap.used.get())
     }
 
-    /// Translates a virtual page number to a physical page number. 
-    /// Can fault and cause pages to be allocated/evicted.
-    pub fn translate(&mut self, addr: u32) -> PageTableResponse {
+    /// Switches to the address space of process `pid`, giving it an empty one the first time.
+    pub fn switch_to(&mut self, pid: u32) {
+        self.mappings.entry(pid).or_default();
+        self.pid = pid;
+        for pool in self.huge.iter_mut() {
+            pool.table.switch_to(pid);
+        }
+    }
+
+    /// The running process's vpn -> frame mapping
+    fn mapping(&self) -> &HashMap<u64, usize> {
+        &self.mappings[&self.pid]
+    }
+
+    /// Translates a virtual page number to a physical page number.
+    /// Can fault and cause pages to be allocated/evicted, which fails if swap runs out.
+    pub fn translate(&mut self, addr: u64, is_write: bool) -> Result<PageTableResponse, Box<dyn Error>> {
         let (vpn, page_offset) = bits::split_at(addr, self.config.offset_size);
 
-        match self.entries.lookup(vpn) {
-            Some(ppn) => {
-                let res = QueryResult::Hit;
-                PageTableResponse { 
-                    vpn: vpn,
-                    ppn,
-                    page_offset,
-                    res: res, 
-                    evicted_ppn: None 
-                }
-            },
+        self.clock += 1;
+        self.disk_writes = 0;
+        self.disk_reads = 0;
+        if let PageReplacement::Aging { interval, .. } = self.config.replacement {
+            self.since_aging += 1;
+            if self.since_aging >= interval {
+                self.since_aging = 0;
+                self.age_pages();
+            }
+        }
+
+        if let Some(size) = self.huge_size_of(addr) {
+            return self.translate_huge(size, addr, is_write);
+        }
+
+        let (slot, res, evicted_ppn) = match self.mapping().get(&vpn) {
+            Some(&slot) => (slot, QueryResult::Hit, None),
             // Page fault: No page was found, so we must insert one (and optionally evict one)
             None => {
-                let res = QueryResult::Miss;
-                let (ppn, evicted_ppn) = self.entries.push(vpn);
-                PageTableResponse { 
-                    vpn: vpn,
-                    ppn,
-                    page_offset,
-                    res: res,
-                    evicted_ppn 
+                if let Some(size) = self.promotion(vpn, addr) {
+                    return self.promote(size, addr, is_write);
                 }
+                let (slot, evicted_ppn) = self.load(vpn)?;
+                (slot, QueryResult::Miss, evicted_ppn)
             }
+        };
+
+        let entry = &mut self.frames[slot];
+        entry.referenced = true;
+        entry.dirty |= is_write;
+        entry.last_use = self.clock;
+        let ppn = entry.ppn;
+
+        Ok(PageTableResponse {
+            vpn,
+            ppn,
+            page_offset,
+            res,
+            evicted_ppns: evicted_ppn.into_iter().collect(),
+            disk_writes: self.disk_writes,
+            disk_reads: self.disk_reads,
+            page_shift: 0,
+            promoted: false,
+        })
+    }
+
+    /// Translates an address that is mapped by a huge page, in that size's pool. The pool
+    /// works in huge pages, so its page numbers are scaled back to base pages.
+    fn translate_huge(&mut self, size: usize, addr: u64, is_write: bool) -> Result<PageTableResponse, Box<dyn Error>> {
+        let (vpn, page_offset) = bits::split_at(addr, self.config.offset_size);
+        let pool = &mut self.huge[size];
+        let response = pool.table.translate(addr, is_write)?;
+        let (shift, base_ppn) = (pool.shift, pool.base_ppn);
+        let first_ppn = |huge_ppn: u64| base_ppn + (huge_ppn << shift);
+
+        Ok(PageTableResponse {
+            vpn,
+            ppn: first_ppn(response.ppn) + (vpn & ((1 << shift) - 1)),
+            page_offset,
+            res: response.res,
+            evicted_ppns: response.evicted_ppns.iter()
+                .flat_map(|&huge_ppn| first_ppn(huge_ppn)..first_ppn(huge_ppn) + (1 << shift))
+                .collect(),
+            disk_writes: self.disk_writes + response.disk_writes,
+            disk_reads: self.disk_reads + response.disk_reads,
+            page_shift: shift,
+            promoted: false,
+        })
+    }
+
+    /// The huge page size that maps `addr`, either because it's in one of the configured ranges
+    /// or because its region was promoted and is still in memory.
+    fn huge_size_of(&self, addr: u64) -> Option<usize> {
+        let huge = self.config.huge?;
+        if let Some(range) = huge.ranges.iter().flatten().find(|r| (r.start..=r.end).contains(&addr)) {
+            return Some(range.size);
         }
+        let (vpn, _page_offset) = bits::split_at(addr, self.config.offset_size);
+        self.huge.iter().rposition(|pool| pool.table.mapping().contains_key(&(vpn >> pool.shift)))
+    }
+
+    /// The biggest huge page size whose region around `vpn` has enough pages in memory
+    /// (counting the one about to be loaded) to be promoted. Regions that overlap a configured
+    /// range are left alone.
+    fn promotion(&self, vpn: u64, addr: u64) -> Option<usize> {
+        let huge = self.config.huge?;
+        let threshold = huge.promotion_threshold? as u64;
+        (0..self.huge.len()).rev().find(|&size| {
+            let shift = self.huge[size].shift;
+            let region = vpn >> shift;
+            let region_bytes = 1u64 << (shift + self.config.offset_size);
+            let start = addr & !(region_bytes - 1);
+            let overlaps_range = huge.ranges.iter().flatten()
+                .any(|r| start <= r.end && r.start < start + region_bytes);
+
+            let resident = self.mapping().keys().filter(|&&v| v >> shift == region).count() as u64
+                + self.huge[..size].iter()
+                    .map(|pool| pool.table.mapping().keys()
+                        .filter(|&&v| (v << pool.shift) >> shift == region)
+                        .count() as u64 * (1 << pool.shift))
+                    .sum::<u64>();
+            !overlaps_range && (resident + 1) * 100 >= threshold * (1 << shift)
+        })
     }
 
-    
+    /// Replaces every smaller page in the region around `addr` with one huge page. Their data
+    /// is copied in memory, so only the faulting page comes from disk; if any of them were
+    /// dirty, so is the huge page.
+    fn promote(&mut self, size: usize, addr: u64, is_write: bool) -> Result<PageTableResponse, Box<dyn Error>> {
+        let (vpn, _page_offset) = bits::split_at(addr, self.config.offset_size);
+        let shift = self.huge[size].shift;
+        let region = vpn >> shift;
+        let mut merged = Vec::new();
+        let mut dirty = false;
+
+        let mut vpns: Vec<u64> = self.mapping().keys().filter(|&&v| v >> shift == region).copied().collect();
+        vpns.sort_unstable();
+        for v in vpns {
+            let (ppn, was_dirty) = self.release(v);
+            merged.push(ppn);
+            dirty |= was_dirty;
+        }
+        for pool in self.huge[..size].iter_mut() {
+            let mut vpns: Vec<u64> = pool.table.mapping().keys()
+                .filter(|&&v| (v << pool.shift) >> shift == region)
+                .copied()
+                .collect();
+            vpns.sort_unstable();
+            for v in vpns {
+                let (huge_ppn, was_dirty) = pool.table.release(v);
+                let first_ppn = pool.base_ppn + (huge_ppn << pool.shift);
+                merged.extend(first_ppn..first_ppn + (1 << pool.shift));
+                dirty |= was_dirty;
+            }
+        }
+
+        let mut response = self.translate_huge(size, addr, is_write || dirty)?;
+        merged.append(&mut response.evicted_ppns);
+        response.evicted_ppns = merged;
+        response.promoted = true;
+        Ok(response)
+    }
+
+    /// Unmaps a page without writing it out, leaving its frame free. Returns the frame and
+    /// whether the page was dirty.
+    fn release(&mut self, vpn: u64) -> (u64, bool) {
+        let pid = self.pid;
+        let slot = self.mappings.get_mut(&pid).and_then(|m| m.remove(&vpn)).expect("only resident pages are released");
+        self.free.push(slot);
+        (self.frames[slot].ppn, self.frames[slot].dirty)
+    }
+
+    /// The PTEs a page table walk for the page holding `addr` reads, root first. A flat page
+    /// table has none.
+    pub fn walk(&mut self, addr: u64) -> Vec<WalkStep> {
+        let (vpn, _page_offset) = bits::split_at(addr, self.config.offset_size);
+        let leaf_shift = self.huge_size_of(addr).map_or(0, |size| self.huge[size].shift);
+        self.radix.as_mut().map_or_else(Vec::new, |radix| radix.walk(self.pid, vpn, leaf_shift))
+    }
+
+    /// Brings a page into memory, evicting a page if every frame is in use. Only a page with a
+    /// copy in swap is read from disk (a major fault); one that has never been written out starts
+    /// zeroed (a minor fault). Returns the page's index into `frames` and the evicted frame.
+    ///
+    /// Under page coloring, the page takes a frame of its color whenever there is one, evicting
+    /// only among the frames of that color once memory is full.
+    fn load(&mut self, vpn: u64) -> Result<(usize, Option<u64>), Box<dyn Error>> {
+        let color = self.allocator.wanted_color(self.pid, vpn);
+        let free = self.free.iter().rposition(|&slot| self.allocator.fits(self.frames[slot].ppn, color))
+            .or_else(|| self.free.len().checked_sub(1));
+        let (slot, ppn, evicted_ppn) = if let Some(i) = free {
+            let slot = self.free.remove(i);
+            (slot, self.frames[slot].ppn, None)
+        } else if self.frames.len() >= self.config.physical_pages as usize {
+            let slot = self.victim(color.filter(|&color| self.allocator.has_frames(color)))?;
+            let evicted = self.frames[slot];
+            if evicted.dirty {
+                self.write_out(evicted.pid, evicted.vpn)?;
+            }
+            if let Some(mapping) = self.mappings.get_mut(&evicted.pid) {
+                mapping.remove(&evicted.vpn);
+            }
+            (slot, evicted.ppn, Some(evicted.ppn))
+        // Otherwise, allocate a new frame
+        } else {
+            (self.frames.len(), self.allocator.allocate(color), None)
+        };
+
+        let entry = PageTableEntry {
+            pid: self.pid,
+            vpn,
+            ppn,
+            referenced: false,
+            dirty: false,
+            loaded_at: self.clock,
+            last_use: self.clock,
+            age: 0,
+        };
+        if slot < self.frames.len() {
+            self.frames[slot] = entry;
+        } else {
+            self.frames.push(entry);
+        }
+        let pid = self.pid;
+        self.mappings.entry(pid).or_default().insert(vpn, slot);
+        if self.swap.as_ref().map_or(true, |swap| swap.swapped.contains(&(pid, vpn))) {
+            self.disk_reads += 1;
+        }
+
+        Ok((slot, evicted_ppn))
+    }
+
+    /// Writes a dirty page out to disk, into swap if it is modeled
+    fn write_out(&mut self, pid: u32, vpn: u64) -> Result<(), Box<dyn Error>> {
+        self.disk_writes += 1;
+        match self.swap.as_mut() {
+            Some(swap) => swap.write_out(pid, vpn),
+            None => Ok(()),
+        }
+    }
+
+    /// Picks the frame to evict according to the replacement policy, among the frames of `color`
+    /// if it is given
+    fn victim(&mut self, color: Option<u64>) -> Result<usize, Box<dyn Error>> {
+        let victim = match self.config.replacement {
+            PageReplacement::Lru => self.oldest_by(color, |e| e.last_use),
+            PageReplacement::Fifo => self.oldest_by(color, |e| e.loaded_at),
+            // the first turn clears every referenced bit, so this always finds a victim
+            PageReplacement::Clock => self.sweep(color, |_, e| {
+                if e.referenced {
+                    e.referenced = false;
+                    false
+                } else {
+                    true
+                }
+            }).unwrap_or(self.hand),
+            PageReplacement::EnhancedSecondChance => self.enhanced_second_chance(color),
+            PageReplacement::Aging { .. } => self.oldest_by(color, |e| (e.age, e.last_use)),
+            PageReplacement::WsClock { window } => self.ws_clock(color, window as u64)?,
+        };
+        Ok(victim)
+    }
+
+    /// Whether the page in `frames[slot]` may be evicted for a page of `color`
+    fn eligible(&self, slot: usize, color: Option<u64>) -> bool {
+        self.allocator.fits(self.frames[slot].ppn, color)
+    }
+
+    /// The frame of `color` with the smallest key
+    fn oldest_by<K: Ord>(&self, color: Option<u64>, key: impl Fn(&PageTableEntry) -> K) -> usize {
+        (0..self.frames.len())
+            .filter(|&slot| self.eligible(slot, color))
+            .min_by_key(|&slot| key(&self.frames[slot]))
+            .unwrap_or(0)
+    }
+
+    /// Advances the clock hand until `evict` picks a frame of `color`, giving up after two full
+    /// turns. Frames of other colors are passed over untouched. Leaves the hand just past the
+    /// victim.
+    fn sweep(&mut self, color: Option<u64>, mut evict: impl FnMut(u64, &mut PageTableEntry) -> bool) -> Option<usize> {
+        for _ in 0..2 * self.frames.len() {
+            let slot = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            if self.eligible(slot, color) && evict(self.clock, &mut self.frames[slot]) {
+                return Some(slot);
+            }
+        }
+        None
+    }
+
+    /// Enhanced second chance: prefer pages in the lowest (referenced, dirty) class, looking for
+    /// (0, 0) first and then (0, 1) while clearing referenced bits along the way. Only frames
+    /// of `color` are looked at, and the caller makes sure there is one. Two rounds are enough:
+    /// a first round that finds nothing has cleared every eligible referenced bit.
+    fn enhanced_second_chance(&mut self, color: Option<u64>) -> usize {
+        let len = self.frames.len();
+        for _ in 0..2 {
+            if let Some(slot) = (0..len).map(|i| (self.hand + i) % len)
+                .filter(|&slot| self.eligible(slot, color))
+                .find(|&slot| !self.frames[slot].referenced && !self.frames[slot].dirty) {
+                self.hand = (slot + 1) % len;
+                return slot;
+            }
+            for i in 0..len {
+                let slot = (self.hand + i) % len;
+                if !self.eligible(slot, color) {
+                    continue;
+                }
+                if !self.frames[slot].referenced {
+                    self.hand = (slot + 1) % len;
+                    return slot;
+                }
+                self.frames[slot].referenced = false;
+            }
+        }
+        self.hand
+    }
+
+    /// WSClock: evict an unreferenced page that has fallen out of the working set (not used in
+    /// the last `window` references). Dirty pages that have fallen out get cleaned (written to
+    /// disk) instead, and become candidates on the next pass.
+    fn ws_clock(&mut self, color: Option<u64>, window: u64) -> Result<usize, Box<dyn Error>> {
+        let mut cleaned = Vec::new();
+        let victim = self.sweep(color, |now, e| {
+            if e.referenced {
+                e.referenced = false;
+                e.last_use = now;
+                false
+            } else if now - e.last_use <= window {
+                false
+            } else if e.dirty {
+                e.dirty = false;
+                cleaned.push((e.pid, e.vpn));
+                false
+            } else {
+                true
+            }
+        });
+        for (pid, vpn) in cleaned {
+            self.write_out(pid, vpn)?;
+        }
+        // Everything is in the working set: evict the least recently used page
+        Ok(victim.unwrap_or_else(|| self.oldest_by(color, |e| e.last_use)))
+    }
+
+    /// Shifts every page's referenced bit into the top of its aging counter
+    fn age_pages(&mut self) {
+        if let PageReplacement::Aging { counter_bits, .. } = self.config.replacement {
+            for e in self.frames.iter_mut() {
+                e.age = (e.age >> 1) | ((e.referenced as u32) << (counter_bits - 1));
+                e.referenced = false;
+            }
+        }
+    }
 
     /* Simply converts an addr into a ppn and offset based on config (no translation)
     pub fn passthrough(&self, addr: u32) -> PageTableResponse {
@@ -77,77 +677,170 @@ impl PageTable {
     */
 }
 
-/* === LRU Set === */
-
-use std::collections::VecDeque;
-#[derive(Clone, Debug)]
-/// A simple LRU Set which evicts elements upon insertion such that the set never exceeds `capacity`
-/// This one is tailored for use with the PageTable. DO NOT GENERALIZE THIS AGAIN, PLEASE.
-//
-//  This could be faster. By using a VecDequeue, 'touching' an item of the cache is O(n). Ideally,
-//  some kind of linked hash map could be used for O(1) lookup _and_ O(1) touch. I suspect that
-//  designing that from scratch would have some serious rust shenanigans that I don't want to deal with.
-//  Also, the max set size is like, 16. Just a note for future reference.
-struct LRUTable {
-    inner: VecDeque<PageTableEntry>,
-    capacity: usize,
+impl std::fmt::Debug for PageTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Page Table:")?;
+        for pte in self.frames.iter() {
+            writeln!(f, "\tpid: {} vpn: {} -> ppn: {}{}{}", pte.pid, pte.vpn, pte.ppn,
+                if pte.referenced { " R" } else { "" },
+                if pte.dirty { " D" } else { "" })?;
+        }
+        Ok(())
+    }
 }
 
-impl LRUTable {
-    fn new(capacity: usize) -> Self {
-        let inner = VecDeque::<PageTableEntry>::with_capacity(capacity);
-        LRUTable { inner, capacity }
-    }
-
-    /// Push an item to the LRU Table, potentially evicting the oldest item
-    fn push(&mut self, vpn: u32) -> (u32, Option<u32>) {
-        // If table is full, evict an item
-        let (ppn, evicted_ppn) = if self.inner.len() >= self.capacity {
-            let evicted_ppn = self.inner.pop_back()
-                .map(|entry| {
-                    entry.clone()
-                })
-                .expect("Failed to pop_back of deque, for some reason")
-                .ppn;
-            
-            let ppn = evicted_ppn;
-            (ppn, Some(evicted_ppn))
-        // Otherwise, allocate a new item
-        } else { 
-            let ppn = self.inner.len() as u32;
-            let evicted_ppn = None;
-            (ppn, evicted_ppn)
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// A page table of `frames` 256-byte frames under 16 virtual pages, with `extended` in its
+    /// section of the config. The L2 makes for two page colors.
+    fn page_table(name: &str, frames: u32, extended: &str) -> PageTable {
+        let config = format!("\
+Data TLB configuration
+Number of sets: 4
+Set size: 2
+
+Page Table configuration
+Number of virtual pages: 16
+Number of physical pages: {}
+Page size: 256
+{}
 
-        let entry = PageTableEntry{ vpn, ppn };
-        self.inner.push_front(entry);
+Data Cache configuration
+Number of sets: 4
+Set size: 1
+Line size: 16
+Write through/no write allocate: n
 
-        (ppn, evicted_ppn)
+L2 Cache configuration
+Number of sets: 32
+Set size: 4
+Line size: 16
+Write through/no write allocate: n
+
+Virtual addresses: y
+TLB: y
+L2 cache: y
+", frames, extended);
+        let path = std::env::temp_dir().join(format!("memsim-test-pt-{}-{}.config", name, std::process::id()));
+        std::fs::write(&path, config).unwrap();
+        let config = Config::from_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        PageTable::new(config.pt)
     }
 
-    /// Look up an item in the LRU Set. If found, the item is 'touched' and moved to the front
-    /// of the queue.
-    fn lookup(&mut self, vpn: u32) -> Option<u32> {
-        let item_search = self.inner
-            .iter()
-            .position(|entry| entry.vpn == vpn);
-        
-        if let Some(item_idx) = item_search {
-            let item = self.inner.remove(item_idx).unwrap();
-            self.inner.push_front(item.clone());
-            Some(item.ppn)
-        } else {
-            None
+    /// References page `vpn`, returning the frames evicted to make room for it
+    fn access(pt: &mut PageTable, vpn: u64, is_write: bool) -> Vec<u64> {
+        pt.translate(vpn << 8, is_write).unwrap().evicted_ppns
+    }
+
+    #[test]
+    fn clock_gives_referenced_pages_a_second_chance() {
+        let mut pt = page_table("clock", 4, "Replacement policy: clock");
+        (0..4).for_each(|vpn| assert!(access(&mut pt, vpn, false).is_empty()));
+        // Every page is referenced, so the hand clears them all and comes back round to frame 0
+        assert_eq!(access(&mut pt, 4, false), vec![0]);
+        access(&mut pt, 1, false);
+        assert_eq!(access(&mut pt, 5, false), vec![2]);
+    }
+
+    #[test]
+    fn esc_evicts_the_lowest_referenced_dirty_class_first() {
+        let mut pt = page_table("esc", 4, "Replacement policy: esc");
+        (0..4).for_each(|vpn| assert!(access(&mut pt, vpn, vpn % 2 == 0).is_empty()));
+        // All are referenced: the second round takes the first clean page over the dirty ones
+        assert_eq!(access(&mut pt, 4, false), vec![1]);
+        assert_eq!(access(&mut pt, 5, false), vec![3]);
+        // Only dirty or referenced pages are left, and the first dirty unreferenced one goes
+        assert_eq!(access(&mut pt, 6, false), vec![0]);
+    }
+
+    #[test]
+    fn esc_with_page_coloring_only_evicts_the_page_color() {
+        let mut pt = page_table("esc-colors", 4, "Replacement policy: esc\nFrame allocation: page-coloring");
+        (0..4).for_each(|vpn| assert!(access(&mut pt, vpn, vpn % 2 == 0).is_empty()));
+        // Page 4 needs an even frame, both of which are dirty. The clean odd frames are skipped
+        // without losing their referenced bits.
+        assert_eq!(access(&mut pt, 4, false), vec![0]);
+        assert!(pt.frames[1].referenced && pt.frames[3].referenced);
+    }
+
+    #[test]
+    fn aging_keeps_the_page_referenced_in_more_intervals() {
+        let mut pt = page_table("aging", 2, "Replacement policy: aging\nAging interval: 2");
+        // Page 0 is referenced in all three intervals and page 1 only in the last two. LRU
+        // would evict page 0, since page 1 was used more recently
+        for vpn in [0, 1, 0, 0, 1] {
+            access(&mut pt, vpn, false);
         }
+        assert_eq!(access(&mut pt, 2, false), vec![1]);
     }
-}
 
-impl std::fmt::Debug for PageTable {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "Page Table:")?;
-        for pte in self.entries.inner.iter() {
-            writeln!(f, "\tvpn: {} -> ppn: {}", pte.vpn, pte.ppn)?;
+    #[test]
+    fn ws_clock_cleans_dirty_pages_out_of_the_working_set() {
+        let mut pt = page_table("wsclock", 3, "Replacement policy: wsclock\nWorking set window: 1");
+        for (vpn, is_write) in [(0, false), (1, true), (2, false)] {
+            access(&mut pt, vpn, is_write);
         }
-        Ok(())
+        // Everything is still in the working set, so the least recently used page goes
+        assert_eq!(access(&mut pt, 3, false), vec![0]);
+        access(&mut pt, 3, false);
+        access(&mut pt, 3, false);
+        // Page 1 has left the working set dirty, so it is cleaned and page 2 evicted instead
+        assert_eq!(access(&mut pt, 4, false), vec![2]);
+        assert!(!pt.frames[1].dirty);
+        assert_eq!(pt.frames[1].vpn, 1);
     }
-}
\ No newline at end of file
+
+    /// Every frame of a 10-frame, 4-color memory, in the order `policy` hands them out
+    fn allocate_all(policy: FrameAllocation) -> Vec<u64> {
+        let mut allocator = FrameAllocator::new(policy, 10, 4);
+        (0..10).map(|_| allocator.allocate(None)).collect()
+    }
+
+    #[test]
+    fn sequential_allocation_takes_the_lowest_frame() {
+        assert_eq!(allocate_all(FrameAllocation::Sequential), (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn random_allocation_is_a_permutation() {
+        let frames = allocate_all(FrameAllocation::Random { seed: 7 });
+        let mut sorted = frames.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+        assert_ne!(frames, sorted);
+        assert_eq!(frames, allocate_all(FrameAllocation::Random { seed: 7 }));
+    }
+
+    #[test]
+    fn bin_hopping_cycles_through_the_colors() {
+        let frames = allocate_all(FrameAllocation::BinHopping);
+        let colors: Vec<u64> = frames.iter().map(|ppn| ppn % 4).collect();
+        assert_eq!(colors, vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1]);
+        assert_eq!(frames, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn page_coloring_moves_on_when_a_color_runs_out() {
+        let mut allocator = FrameAllocator::new(FrameAllocation::PageColoring { colors_per_process: None }, 10, 4);
+        let frames: Vec<u64> = [2, 2, 2, 1].iter().map(|&color| allocator.allocate(Some(color))).collect();
+        assert_eq!(frames, vec![2, 6, 3, 1]);
+        assert!(allocator.fits(6, Some(2)));
+        assert!(!allocator.fits(3, Some(2)));
+        assert!(allocator.fits(3, None));
+    }
+
+    #[test]
+    fn page_coloring_partitions_colors_by_process() {
+        let shared = FrameAllocator::new(FrameAllocation::PageColoring { colors_per_process: None }, 16, 4);
+        assert_eq!(shared.wanted_color(1, 7), Some(3));
+        let partitioned = FrameAllocator::new(FrameAllocation::PageColoring { colors_per_process: Some(2) }, 16, 4);
+        assert_eq!(partitioned.wanted_color(0, 5), Some(1));
+        assert_eq!(partitioned.wanted_color(1, 5), Some(3));
+        assert_eq!(partitioned.wanted_color(1, 6), Some(2));
+        assert_eq!(FrameAllocator::new(FrameAllocation::Sequential, 16, 4).wanted_color(1, 7), None);
+    }
+}
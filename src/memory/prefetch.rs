@@ -0,0 +1,176 @@
+use crate::config::Prefetch;
+
+/// Entries in the IP-stride prefetcher's table, which is indexed by PC
+const STRIDE_TABLE_ENTRIES: usize = 64;
+/// Streams the stream prefetcher can follow at once
+const STREAMS: usize = 16;
+/// How far (in lines) an access can be from the end of a stream and still continue it
+const STREAM_WINDOW: i64 = 16;
+
+/// Watches the demand accesses to a cache level and predicts which lines will be wanted next.
+///
+/// Prefetchers work in block numbers (addresses with the line offset removed). The cache decides
+/// which predictions are worth filling.
+pub trait Prefetcher: std::fmt::Debug {
+    /// A demand access to `block` by the instruction at `pc` (if the trace has one) either hit
+    /// or missed. Returns the blocks to prefetch.
+    fn observe(&mut self, pc: Option<u64>, block: u64, hit: bool) -> Vec<u64>;
+}
+
+/// Builds the prefetcher described by the config.
+pub fn new_prefetcher(kind: Prefetch) -> Box<dyn Prefetcher> {
+    match kind {
+        Prefetch::NextLine { degree, distance } => Box::new(NextLine { degree, distance }),
+        Prefetch::IpStride { degree, distance } => Box::new(IpStride::new(degree, distance)),
+        Prefetch::Stream { degree, distance } => Box::new(Stream::new(degree, distance)),
+    }
+}
+
+/// The `degree` blocks `distance`, `distance + 1`, ... strides past `block`.
+fn ahead(block: u64, stride: i64, degree: u32, distance: u32) -> Vec<u64> {
+    (0..degree as i64)
+        .map(|i| block as i64 + stride * (distance as i64 + i))
+        .filter(|&b| b >= 0)
+        .map(|b| b as u64)
+        .collect()
+}
+
+/* === Next-line === */
+
+/// Always fetches the lines just past the one accessed.
+#[derive(Debug)]
+struct NextLine {
+    degree: u32,
+    distance: u32,
+}
+
+impl Prefetcher for NextLine {
+    fn observe(&mut self, _pc: Option<u64>, block: u64, _hit: bool) -> Vec<u64> {
+        ahead(block, 1, self.degree, self.distance)
+    }
+}
+
+/* === IP-stride === */
+
+#[derive(Default, Copy, Clone, Debug)]
+struct StrideEntry {
+    /// The PC that owns the entry (`None` for an unused entry)
+    pc: Option<u64>,
+    last_block: u64,
+    stride: i64,
+    /// Saturating 2-bit counter: prefetch once the same stride has been seen twice in a row
+    confidence: u8,
+}
+
+/// Learns a constant stride between the accesses made by each instruction. Without PCs in the
+/// trace every access looks like it comes from the same instruction, so it only catches a
+/// single global stride.
+#[derive(Debug)]
+struct IpStride {
+    degree: u32,
+    distance: u32,
+    table: Vec<StrideEntry>,
+}
+
+impl IpStride {
+    fn new(degree: u32, distance: u32) -> Self {
+        IpStride { degree, distance, table: vec![StrideEntry::default(); STRIDE_TABLE_ENTRIES] }
+    }
+}
+
+impl Prefetcher for IpStride {
+    fn observe(&mut self, pc: Option<u64>, block: u64, _hit: bool) -> Vec<u64> {
+        let pc = pc.unwrap_or(0);
+        // Fold in the higher bits of the PC, since instructions tend to be aligned
+        let index = (pc ^ (pc >> 6) ^ (pc >> 12)) as usize % STRIDE_TABLE_ENTRIES;
+        let entry = &mut self.table[index];
+        if entry.pc != Some(pc) {
+            *entry = StrideEntry { pc: Some(pc), last_block: block, stride: 0, confidence: 0 };
+            return Vec::new();
+        }
+
+        let stride = block as i64 - entry.last_block as i64;
+        if stride == 0 {
+            return Vec::new();
+        }
+        if stride == entry.stride {
+            entry.confidence = (entry.confidence + 1).min(3);
+        } else if entry.confidence > 0 {
+            entry.confidence -= 1;
+        } else {
+            entry.stride = stride;
+        }
+        entry.last_block = block;
+
+        if entry.confidence >= 2 {
+            ahead(block, entry.stride, self.degree, self.distance)
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/* === Stream === */
+
+#[derive(Copy, Clone, Debug)]
+struct StreamEntry {
+    last_block: u64,
+    /// +1 for ascending, -1 for descending, 0 until the second access
+    direction: i64,
+    confirmed: bool,
+    last_use: u64,
+}
+
+/// Follows up to `STREAMS` sequential streams, ascending or descending. A miss that isn't near
+/// any stream starts a new one (replacing the least recently used); once two accesses have
+/// moved a stream the same way, each further access fetches the lines ahead of it.
+#[derive(Debug)]
+struct Stream {
+    degree: u32,
+    distance: u32,
+    streams: Vec<StreamEntry>,
+    clock: u64,
+}
+
+impl Stream {
+    fn new(degree: u32, distance: u32) -> Self {
+        Stream { degree, distance, streams: Vec::with_capacity(STREAMS), clock: 0 }
+    }
+}
+
+impl Prefetcher for Stream {
+    fn observe(&mut self, _pc: Option<u64>, block: u64, hit: bool) -> Vec<u64> {
+        self.clock += 1;
+        let near = self.streams.iter()
+            .position(|s| (block as i64 - s.last_block as i64).abs() <= STREAM_WINDOW);
+
+        match near {
+            Some(i) => {
+                let stream = &mut self.streams[i];
+                stream.last_use = self.clock;
+                let direction = (block as i64 - stream.last_block as i64).signum();
+                if direction == 0 {
+                    return Vec::new();
+                }
+                stream.confirmed = direction == stream.direction;
+                stream.direction = direction;
+                stream.last_block = block;
+                if stream.confirmed {
+                    ahead(block, direction, self.degree, self.distance)
+                } else {
+                    Vec::new()
+                }
+            },
+            None if !hit => {
+                let stream = StreamEntry { last_block: block, direction: 0, confirmed: false, last_use: self.clock };
+                if self.streams.len() < STREAMS {
+                    self.streams.push(stream);
+                } else if let Some(oldest) = self.streams.iter_mut().min_by_key(|s| s.last_use) {
+                    *oldest = stream;
+                }
+                Vec::new()
+            },
+            None => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,558 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::{
+    config::Replacement,
+    memory::{
+        oracle::FutureUses,
+        stats::DuelStats,
+    },
+    utils::rng::XorShift64,
+};
+
+/// Decides which way of a set gets evicted when a new line needs a home.
+///
+/// A policy instance covers every set of a cache, so that policies which need to see across sets
+/// are possible. Ways are addressed as `(set, way)`. The cache always fills empty ways before
+/// asking for a victim, so `victim` is only called on full sets.
+pub trait ReplacementPolicy: std::fmt::Debug {
+    /// A resident line was hit.
+    fn touch(&mut self, set: usize, way: usize);
+    /// A new line was placed in the way.
+    fn fill(&mut self, set: usize, way: usize);
+    /// The line in the way was invalidated.
+    fn invalidate(&mut self, _set: usize, _way: usize) {}
+    /// Tells the policy which block (or page) a fill put in the way. Only offline policies care.
+    fn place(&mut self, _set: usize, _way: usize, _block: u64) {}
+    /// Picks the way to evict from a full set.
+    fn victim(&mut self, set: usize) -> usize;
+    /// Set dueling results, for policies that duel.
+    fn duel_stats(&self) -> Option<DuelStats> {
+        None
+    }
+}
+
+/// Builds the policy described by the config for a cache with the given geometry.
+pub fn new_policy(kind: Replacement, sets: usize, ways: usize) -> Box<dyn ReplacementPolicy> {
+    match kind {
+        Replacement::Lru => Box::new(Lru::new(sets, ways)),
+        Replacement::Fifo => Box::new(Fifo::new(sets, ways)),
+        Replacement::Random { seed } => Box::new(Random::new(ways, seed)),
+        Replacement::TreePlru => Box::new(TreePlru::new(sets, ways)),
+        Replacement::Lfu => Box::new(Lfu::new(sets, ways)),
+        Replacement::Nru => Box::new(Nru::new(sets, ways)),
+        Replacement::Srrip { rrpv_bits } => {
+            Box::new(Rrip::new(sets, ways, rrpv_bits, 1, 0, Insertion::Static(RripMode::Srrip)))
+        },
+        Replacement::Brrip { rrpv_bits, throttle, seed } => {
+            Box::new(Rrip::new(sets, ways, rrpv_bits, throttle, seed, Insertion::Static(RripMode::Brrip)))
+        },
+        Replacement::Drrip { rrpv_bits, throttle, seed, leader_sets, psel_bits } => {
+            let duel = SetDuel::new(sets, leader_sets as usize, psel_bits);
+            Box::new(Rrip::new(sets, ways, rrpv_bits, throttle, seed, Insertion::Dueling(duel)))
+        },
+    }
+}
+
+/// Index of the way with the smallest key in the set.
+fn min_way<K: Ord>(ways: usize, key: impl Fn(usize) -> K) -> usize {
+    (0..ways).min_by_key(|&way| key(way)).unwrap_or(0)
+}
+
+/* === LRU === */
+
+/// True LRU: every way remembers when it was last used.
+#[derive(Debug)]
+struct Lru {
+    ways: usize,
+    last_use: Vec<u64>,
+    clock: u64,
+}
+
+impl Lru {
+    fn new(sets: usize, ways: usize) -> Self {
+        Lru { ways, last_use: vec![0; sets * ways], clock: 0 }
+    }
+}
+
+impl ReplacementPolicy for Lru {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.last_use[set * self.ways + way] = self.clock;
+    }
+    fn fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        min_way(self.ways, |way| self.last_use[set * self.ways + way])
+    }
+}
+
+/* === FIFO === */
+
+/// Evicts whichever line has been in the set the longest, regardless of use.
+#[derive(Debug)]
+struct Fifo {
+    ways: usize,
+    filled_at: Vec<u64>,
+    clock: u64,
+}
+
+impl Fifo {
+    fn new(sets: usize, ways: usize) -> Self {
+        Fifo { ways, filled_at: vec![0; sets * ways], clock: 0 }
+    }
+}
+
+impl ReplacementPolicy for Fifo {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+    fn fill(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.filled_at[set * self.ways + way] = self.clock;
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        min_way(self.ways, |way| self.filled_at[set * self.ways + way])
+    }
+}
+
+/* === Random === */
+
+/// Evicts a uniformly random way. Seeded so runs are repeatable.
+#[derive(Debug)]
+struct Random {
+    ways: usize,
+    rng: XorShift64,
+}
+
+impl Random {
+    fn new(ways: usize, seed: u64) -> Self {
+        Random { ways, rng: XorShift64::new(seed) }
+    }
+}
+
+impl ReplacementPolicy for Random {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+    fn fill(&mut self, _set: usize, _way: usize) {}
+    fn victim(&mut self, _set: usize) -> usize {
+        self.rng.below(self.ways as u64) as usize
+    }
+}
+
+/* === Tree PLRU === */
+
+/// Binary tree pseudo-LRU. Each set keeps `ways - 1` bits arranged as a heap, where each bit
+/// points toward the half of its subtree that should be evicted next. Needs power of 2 ways,
+/// which the config already guarantees.
+#[derive(Debug)]
+struct TreePlru {
+    ways: usize,
+    // true = the victim is in the right subtree
+    bits: Vec<bool>,
+}
+
+impl TreePlru {
+    fn new(sets: usize, ways: usize) -> Self {
+        TreePlru { ways, bits: vec![false; sets * ways.saturating_sub(1)] }
+    }
+
+    fn node(&mut self, set: usize, node: usize) -> &mut bool {
+        &mut self.bits[set * (self.ways - 1) + node]
+    }
+}
+
+impl ReplacementPolicy for TreePlru {
+    fn touch(&mut self, set: usize, way: usize) {
+        let (mut node, mut lo, mut span) = (0, 0, self.ways);
+        while span > 1 {
+            span /= 2;
+            let right = way >= lo + span;
+            // point away from the way we just used
+            *self.node(set, node) = !right;
+            node = 2 * node + 1 + right as usize;
+            if right {
+                lo += span;
+            }
+        }
+    }
+    fn fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let (mut node, mut lo, mut span) = (0, 0, self.ways);
+        while span > 1 {
+            span /= 2;
+            let right = *self.node(set, node);
+            node = 2 * node + 1 + right as usize;
+            if right {
+                lo += span;
+            }
+        }
+        lo
+    }
+}
+
+/* === LFU === */
+
+/// Evicts the least frequently used line. Ties go to the least recently used of them.
+#[derive(Debug)]
+struct Lfu {
+    ways: usize,
+    uses: Vec<u64>,
+    last_use: Vec<u64>,
+    clock: u64,
+}
+
+impl Lfu {
+    fn new(sets: usize, ways: usize) -> Self {
+        Lfu { ways, uses: vec![0; sets * ways], last_use: vec![0; sets * ways], clock: 0 }
+    }
+}
+
+impl ReplacementPolicy for Lfu {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.clock += 1;
+        self.uses[set * self.ways + way] += 1;
+        self.last_use[set * self.ways + way] = self.clock;
+    }
+    fn fill(&mut self, set: usize, way: usize) {
+        self.uses[set * self.ways + way] = 0;
+        self.touch(set, way);
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let base = set * self.ways;
+        min_way(self.ways, |way| (self.uses[base + way], self.last_use[base + way]))
+    }
+}
+
+/* === NRU === */
+
+/// Not recently used: one reference bit per way. The victim is the first way with a clear bit;
+/// once every bit in a set is set, they are all cleared.
+#[derive(Debug)]
+struct Nru {
+    ways: usize,
+    referenced: Vec<bool>,
+}
+
+impl Nru {
+    fn new(sets: usize, ways: usize) -> Self {
+        Nru { ways, referenced: vec![false; sets * ways] }
+    }
+}
+
+impl ReplacementPolicy for Nru {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.referenced[set * self.ways + way] = true;
+    }
+    fn fill(&mut self, set: usize, way: usize) {
+        self.touch(set, way);
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.referenced[set * self.ways + way] = false;
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let bits = &mut self.referenced[set * self.ways..(set + 1) * self.ways];
+        match bits.iter().position(|r| !r) {
+            Some(way) => way,
+            None => {
+                bits.iter_mut().for_each(|r| *r = false);
+                0
+            }
+        }
+    }
+}
+
+/* === RRIP === */
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum RripMode {
+    /// Static RRIP: insert with a long re-reference prediction.
+    Srrip,
+    /// Bimodal RRIP: insert with a distant prediction, except for 1 in `throttle` fills.
+    Brrip,
+}
+
+/// Set dueling between SRRIP and BRRIP. A few leader sets are hardwired to each policy, and
+/// every miss in a leader set nudges the PSEL counter away from that policy. The remaining
+/// (follower) sets use whichever policy PSEL currently favors.
+#[derive(Debug)]
+struct SetDuel {
+    // leader sets are every `spacing`th set (SRRIP), and the set right after it (BRRIP)
+    spacing: usize,
+    psel: u32,
+    psel_max: u32,
+    stats: DuelStats,
+}
+
+impl SetDuel {
+    fn new(sets: usize, leader_sets: usize, psel_bits: u32) -> Self {
+        let spacing = (sets / leader_sets).max(2);
+        let psel_max = (1 << psel_bits) - 1;
+        SetDuel { spacing, psel: psel_max / 2 + 1, psel_max, stats: DuelStats::default() }
+    }
+
+    fn leader(&self, set: usize) -> Option<RripMode> {
+        match set % self.spacing {
+            0 => Some(RripMode::Srrip),
+            1 => Some(RripMode::Brrip),
+            _ => None,
+        }
+    }
+
+    /// Picks the insertion policy for a miss in `set`, updating PSEL if it's a leader.
+    fn on_miss(&mut self, set: usize) -> RripMode {
+        match self.leader(set) {
+            Some(RripMode::Srrip) => {
+                self.psel = (self.psel + 1).min(self.psel_max);
+                RripMode::Srrip
+            },
+            Some(RripMode::Brrip) => {
+                self.psel = self.psel.saturating_sub(1);
+                RripMode::Brrip
+            },
+            None if self.psel > self.psel_max / 2 => {
+                self.stats.brrip_wins += 1;
+                RripMode::Brrip
+            },
+            None => {
+                self.stats.srrip_wins += 1;
+                RripMode::Srrip
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Insertion {
+    Static(RripMode),
+    Dueling(SetDuel),
+}
+
+/// Re-reference interval prediction (Jaleel et al., ISCA 2010). Each way has an RRPV; hits
+/// reset it to 0, and the victim is the first way predicted to be re-referenced in the distant
+/// future (RRPV at max), aging the whole set until one is.
+#[derive(Debug)]
+struct Rrip {
+    ways: usize,
+    rrpv: Vec<u8>,
+    max_rrpv: u8,
+    throttle: u64,
+    rng: XorShift64,
+    insertion: Insertion,
+}
+
+impl Rrip {
+    fn new(sets: usize, ways: usize, rrpv_bits: u32, throttle: u32, seed: u64, insertion: Insertion) -> Self {
+        let max_rrpv = ((1u32 << rrpv_bits) - 1) as u8;
+        Rrip {
+            ways,
+            rrpv: vec![max_rrpv; sets * ways],
+            max_rrpv,
+            throttle: throttle as u64,
+            rng: XorShift64::new(seed),
+            insertion,
+        }
+    }
+}
+
+impl ReplacementPolicy for Rrip {
+    fn touch(&mut self, set: usize, way: usize) {
+        self.rrpv[set * self.ways + way] = 0;
+    }
+    fn fill(&mut self, set: usize, way: usize) {
+        let mode = match &mut self.insertion {
+            Insertion::Static(mode) => *mode,
+            Insertion::Dueling(duel) => duel.on_miss(set),
+        };
+        let long = self.max_rrpv.saturating_sub(1);
+        self.rrpv[set * self.ways + way] = match mode {
+            RripMode::Srrip => long,
+            RripMode::Brrip if self.rng.below(self.throttle) == 0 => long,
+            RripMode::Brrip => self.max_rrpv,
+        };
+    }
+    fn invalidate(&mut self, set: usize, way: usize) {
+        self.rrpv[set * self.ways + way] = self.max_rrpv;
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let rrpvs = &mut self.rrpv[set * self.ways..(set + 1) * self.ways];
+        loop {
+            if let Some(way) = rrpvs.iter().position(|&r| r >= self.max_rrpv) {
+                return way;
+            }
+            rrpvs.iter_mut().for_each(|r| *r += 1);
+        }
+    }
+    fn duel_stats(&self) -> Option<DuelStats> {
+        match &self.insertion {
+            Insertion::Dueling(duel) => Some(duel.stats),
+            Insertion::Static(_) => None,
+        }
+    }
+}
+
+/* === Belady's OPT === */
+
+/// Belady's optimal replacement: evicts the line whose next reference is furthest in the
+/// future. Needs the whole trace up front, so it can only be used offline.
+#[derive(Debug)]
+pub struct Opt {
+    ways: usize,
+    blocks: Vec<u64>,
+    future: Rc<FutureUses>,
+    now: Rc<Cell<usize>>,
+}
+
+impl Opt {
+    pub fn new(sets: usize, ways: usize, future: Rc<FutureUses>, now: Rc<Cell<usize>>) -> Self {
+        Opt { ways, blocks: vec![0; sets * ways], future, now }
+    }
+}
+
+impl ReplacementPolicy for Opt {
+    fn touch(&mut self, _set: usize, _way: usize) {}
+    fn fill(&mut self, _set: usize, _way: usize) {}
+    fn place(&mut self, set: usize, way: usize, block: u64) {
+        self.blocks[set * self.ways + way] = block;
+    }
+    fn victim(&mut self, set: usize) -> usize {
+        let now = self.now.get();
+        (0..self.ways)
+            .max_by_key(|&way| self.future.next_use(self.blocks[set * self.ways + way], now))
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills every way of a four-way set, in way order
+    fn filled<P: ReplacementPolicy>(mut policy: P) -> P {
+        (0..4).for_each(|way| policy.fill(0, way));
+        policy
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used_way() {
+        let mut policy = filled(Lru::new(1, 4));
+        assert_eq!(policy.victim(0), 0);
+        policy.touch(0, 0);
+        assert_eq!(policy.victim(0), 1);
+        policy.touch(0, 1);
+        assert_eq!(policy.victim(0), 2);
+    }
+
+    #[test]
+    fn fifo_evicts_the_oldest_fill_whatever_its_use() {
+        let mut policy = filled(Fifo::new(1, 4));
+        policy.touch(0, 0);
+        assert_eq!(policy.victim(0), 0);
+        policy.fill(0, 0);
+        assert_eq!(policy.victim(0), 1);
+    }
+
+    #[test]
+    fn random_repeats_its_victims_for_a_seed() {
+        let victims = |seed| {
+            let mut policy = filled(Random::new(4, seed));
+            (0..8).map(|_| policy.victim(0)).collect::<Vec<_>>()
+        };
+        assert_eq!(victims(1), vec![1, 1, 3, 1, 0, 1, 1, 1]);
+        assert_eq!(victims(1), victims(1));
+    }
+
+    #[test]
+    fn tree_plru_evicts_from_the_other_half_after_a_touch() {
+        let mut policy = filled(TreePlru::new(1, 4));
+        assert_eq!(policy.victim(0), 0);
+        policy.touch(0, 0);
+        assert_eq!(policy.victim(0), 2);
+        policy.touch(0, 2);
+        assert_eq!(policy.victim(0), 1);
+    }
+
+    #[test]
+    fn lfu_breaks_ties_by_recency() {
+        let mut policy = filled(Lfu::new(1, 4));
+        policy.touch(0, 0);
+        policy.touch(0, 0);
+        policy.touch(0, 1);
+        // Ways 2 and 3 were used once each, and way 2 longer ago
+        assert_eq!(policy.victim(0), 2);
+        policy.touch(0, 2);
+        assert_eq!(policy.victim(0), 3);
+        policy.touch(0, 3);
+        assert_eq!(policy.victim(0), 1);
+    }
+
+    #[test]
+    fn nru_clears_the_bits_once_every_way_is_referenced() {
+        let mut policy = filled(Nru::new(1, 4));
+        assert_eq!(policy.victim(0), 0);
+        assert_eq!(policy.referenced, vec![false; 4]);
+        policy.touch(0, 0);
+        assert_eq!(policy.victim(0), 1);
+        (1..4).for_each(|way| policy.touch(0, way));
+        assert_eq!(policy.victim(0), 0);
+        assert_eq!(policy.referenced, vec![false; 4]);
+    }
+
+    /// One set of four ways with 2-bit RRPVs
+    fn rrip(mode: RripMode, throttle: u32) -> Rrip {
+        Rrip::new(1, 4, 2, throttle, 1, Insertion::Static(mode))
+    }
+
+    #[test]
+    fn srrip_inserts_long_and_ages_the_set_to_a_victim() {
+        let mut policy = rrip(RripMode::Srrip, 1);
+        (0..4).for_each(|way| policy.fill(0, way));
+        assert_eq!(policy.rrpv, vec![2; 4]);
+        policy.touch(0, 1);
+        assert_eq!(policy.victim(0), 0);
+        assert_eq!(policy.rrpv, vec![3, 1, 3, 3]);
+        policy.fill(0, 0);
+        assert_eq!(policy.victim(0), 2);
+    }
+
+    #[test]
+    fn brrip_inserts_distant_except_one_in_throttle() {
+        let mut policy = rrip(RripMode::Brrip, u32::MAX);
+        (0..4).for_each(|way| policy.fill(0, way));
+        assert_eq!(policy.rrpv, vec![3; 4]);
+        // A distant line is the victim straight away, without aging the others
+        policy.touch(0, 0);
+        assert_eq!(policy.victim(0), 1);
+        assert_eq!(policy.rrpv, vec![0, 3, 3, 3]);
+
+        let mut policy = rrip(RripMode::Brrip, 1);
+        (0..4).for_each(|way| policy.fill(0, way));
+        assert_eq!(policy.rrpv, vec![2; 4]);
+    }
+
+    #[test]
+    fn set_duel_leaves_follower_sets() {
+        let duel = SetDuel::new(64, 16, 10);
+        let leaders = |mode| (0..64).filter(|&set| duel.leader(set) == Some(mode)).count();
+        assert_eq!(leaders(RripMode::Srrip), 16);
+        assert_eq!(leaders(RripMode::Brrip), 16);
+        assert_eq!((0..64).filter(|&set| duel.leader(set).is_none()).count(), 32);
+        assert_eq!(duel.leader(0), Some(RripMode::Srrip));
+        assert_eq!(duel.leader(1), Some(RripMode::Brrip));
+        assert_eq!(duel.leader(2), None);
+    }
+
+    #[test]
+    fn set_duel_followers_take_the_policy_missing_less() {
+        let mut duel = SetDuel::new(64, 16, 4);
+        // Misses in SRRIP's leader sets push followers to BRRIP, and the other way round
+        for _ in 0..4 {
+            assert_eq!(duel.on_miss(0), RripMode::Srrip);
+        }
+        assert_eq!(duel.on_miss(2), RripMode::Brrip);
+        for _ in 0..8 {
+            assert_eq!(duel.on_miss(1), RripMode::Brrip);
+        }
+        assert_eq!(duel.on_miss(3), RripMode::Srrip);
+        assert_eq!((duel.stats.srrip_wins, duel.stats.brrip_wins), (1, 1));
+    }
+}
@@ -0,0 +1,724 @@
+use std::collections::BTreeMap;
+
+use crate::{config::HugePageConfig, memory::QueryResult, utils::bits};
+
+/// Hit/miss tally for one of the memory subsystems.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct QueryCounter {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl QueryCounter {
+    pub fn record(&mut self, result: QueryResult) {
+        match result {
+            QueryResult::Hit => self.hits += 1,
+            QueryResult::Miss => self.misses += 1,
+        }
+    }
+
+    /// Fraction of queries that hit, or `None` if the subsystem was never queried.
+    pub fn hit_ratio(&self) -> Option<f64> {
+        ratio(self.hits, self.hits + self.misses)
+    }
+}
+
+/// How many follower-set fills each side of a DRRIP set duel got to decide.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct DuelStats {
+    pub srrip_wins: u64,
+    pub brrip_wins: u64,
+}
+
+impl std::ops::AddAssign<&DuelStats> for DuelStats {
+    fn add_assign(&mut self, other: &Self) {
+        self.srrip_wins += other.srrip_wins;
+        self.brrip_wins += other.brrip_wins;
+    }
+}
+
+/// What a level's prefetcher did, and how much of it paid off.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct PrefetchStats {
+    /// Lines filled by the prefetcher
+    pub issued: u64,
+    /// Prefetched lines that were later hit by a demand access
+    pub useful: u64,
+    /// Prefetched lines that were evicted before any demand access used them
+    pub unused_evicted: u64,
+}
+
+impl std::ops::AddAssign<&PrefetchStats> for PrefetchStats {
+    fn add_assign(&mut self, other: &Self) {
+        self.issued += other.issued;
+        self.useful += other.useful;
+        self.unused_evicted += other.unused_evicted;
+    }
+}
+
+impl PrefetchStats {
+    /// Fraction of prefetched lines that were used.
+    pub fn accuracy(&self) -> Option<f64> {
+        ratio(self.useful, self.issued)
+    }
+
+    /// Fraction of would-be misses that prefetching turned into hits, given the misses left.
+    pub fn coverage(&self, misses: u64) -> Option<f64> {
+        ratio(self.useful, self.useful + misses)
+    }
+}
+
+/// Walks of a radix page table, and how the page walk caches shortened them.
+#[derive(Default, Clone, Debug)]
+pub struct WalkStats {
+    /// Page table walks, one per TLB miss (or per access with the TLB disabled)
+    pub walks: u64,
+    /// PTEs read by walks, not counting the levels skipped by a walk cache hit
+    pub pte_reads: u64,
+    /// One per upper level of the page table, for levels with a walk cache
+    pub caches: Vec<Option<QueryCounter>>,
+}
+
+impl WalkStats {
+    /// Average memory references each walk made.
+    pub fn refs_per_walk(&self) -> Option<f64> {
+        ratio(self.pte_reads, self.walks)
+    }
+}
+
+/// Which page sizes translated the accesses.
+#[derive(Default, Clone, Debug)]
+pub struct HugePageStats {
+    /// Each page size in bytes, the base page size first
+    pub sizes: Vec<u64>,
+    /// log2 of the base pages in a page of each size
+    pub shifts: Vec<u32>,
+    /// Accesses translated by a page of each size
+    pub refs: Vec<u64>,
+    /// Regions promoted to a huge page
+    pub promotions: u64,
+}
+
+impl HugePageStats {
+    pub fn new(page_size: u64, huge: &HugePageConfig) -> Self {
+        let sizes = huge.sizes.iter().flatten();
+        HugePageStats {
+            sizes: std::iter::once(page_size).chain(sizes.clone().map(|s| s.size)).collect(),
+            shifts: std::iter::once(0).chain(sizes.clone().map(|s| s.shift)).collect(),
+            refs: vec![0; sizes.count() + 1],
+            promotions: 0,
+        }
+    }
+}
+
+/// Page faults told apart by whether they had to read the disk, and swap's traffic.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct SwapStats {
+    /// Faults on pages with nothing in swap, which start zeroed
+    pub minor_faults: u64,
+    /// Faults on pages that were swapped out, which are read back in
+    pub major_faults: u64,
+    pub disk_reads: u64,
+    pub disk_writes: u64,
+    /// Base pages of swap in use at the end, and in all
+    pub used: u64,
+    pub capacity: u64,
+}
+
+/// What the write buffer behind the DC did with the writes sent into it.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct WriteBufferStats {
+    /// Writes the DC sent on below, which would all have gone to the next level without a buffer
+    pub writes: u64,
+    /// Writes combined with one already waiting for the same line
+    pub coalesced: u64,
+    /// Writes that found the buffer full and had to wait for its oldest line to drain
+    pub stalls: u64,
+    /// Reads that missed in the DC and were handed their line by the buffer
+    pub forwarded: u64,
+    /// Lines written to the next level, counting those still waiting at the end
+    pub drained: u64,
+}
+
+impl WriteBufferStats {
+    pub fn coalescing_rate(&self) -> Option<f64> {
+        ratio(self.coalesced, self.writes)
+    }
+
+    /// The share of writes the next level was spared
+    pub fn write_reduction(&self) -> Option<f64> {
+        ratio(self.writes - self.drained, self.writes)
+    }
+}
+
+/// Dirty data leaving each level: lines written back out of each cache level, and dirty pages
+/// written out to disk.
+#[derive(Default, Clone, Debug)]
+pub struct Writebacks {
+    /// One per configured cache level
+    pub caches: Vec<u64>,
+    pub disk: u64,
+}
+
+impl Writebacks {
+    pub fn new(levels: usize) -> Self {
+        Writebacks { caches: vec![0; levels], disk: 0 }
+    }
+}
+
+impl std::ops::AddAssign<&Writebacks> for Writebacks {
+    fn add_assign(&mut self, other: &Self) {
+        for (total, n) in self.caches.iter_mut().zip(other.caches.iter()) {
+            *total += n;
+        }
+        self.disk += other.disk;
+    }
+}
+
+/// Cycles spent in each part of the hierarchy, by one access or by every access so far.
+#[derive(Default, Clone, Debug)]
+pub struct Latency {
+    pub tlb: u64,
+    pub stlb: u64,
+    /// Page table walks, along with their PTE reads
+    pub walk: u64,
+    /// One per configured cache level, counting the writebacks and write-throughs it takes in
+    pub caches: Vec<u64>,
+    /// Counting the writebacks and write-throughs it takes in
+    pub memory: u64,
+    /// Pages read in on faults, and dirty pages written out to make room for them
+    pub disk: u64,
+}
+
+impl Latency {
+    pub fn new(levels: usize) -> Self {
+        Latency { caches: vec![0; levels], ..Default::default() }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.tlb + self.stlb + self.walk + self.caches.iter().sum::<u64>() + self.memory + self.disk
+    }
+}
+
+impl std::ops::AddAssign<&Latency> for Latency {
+    fn add_assign(&mut self, other: &Self) {
+        self.tlb += other.tlb;
+        self.stlb += other.stlb;
+        self.walk += other.walk;
+        for (total, n) in self.caches.iter_mut().zip(other.caches.iter()) {
+            *total += n;
+        }
+        self.memory += other.memory;
+        self.disk += other.disk;
+    }
+}
+
+/// What one DRAM bank's row buffer did.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct BankStats {
+    pub requests: u64,
+    pub row_hits: u64,
+    /// Requests that found no row open
+    pub row_empty: u64,
+    /// Requests that found another row open
+    pub row_conflicts: u64,
+    /// Cycles spent serving requests
+    pub busy: u64,
+}
+
+/// Row buffer outcomes of the requests to DRAM, by bank.
+#[derive(Default, Clone, Debug)]
+pub struct DramStats {
+    /// Every bank of every rank of every channel, in that order
+    pub banks: Vec<BankStats>,
+    pub ranks: u32,
+    /// Banks in each rank
+    pub banks_per_rank: u32,
+}
+
+impl DramStats {
+    pub fn new(channels: u32, ranks: u32, banks: u32) -> Self {
+        DramStats {
+            banks: vec![BankStats::default(); (channels * ranks * banks) as usize],
+            ranks,
+            banks_per_rank: banks,
+        }
+    }
+
+    /// Every bank's counts added together.
+    pub fn total(&self) -> BankStats {
+        self.banks.iter().fold(BankStats::default(), |total, bank| BankStats {
+            requests: total.requests + bank.requests,
+            row_hits: total.row_hits + bank.row_hits,
+            row_empty: total.row_empty + bank.row_empty,
+            row_conflicts: total.row_conflicts + bank.row_conflicts,
+            busy: total.busy + bank.busy,
+        })
+    }
+
+    /// "ch0 ra1 ba3" for a bank's index.
+    fn bank_name(&self, bank: usize) -> String {
+        let (banks, ranks) = (self.banks_per_rank as usize, self.ranks as usize);
+        format!("ch{} ra{} ba{}", bank / (banks * ranks), bank / banks % ranks, bank % banks)
+    }
+}
+
+/// Lines moved or dropped to keep each level's inclusion policy.
+#[derive(Default, Clone, Debug)]
+pub struct InclusionStats {
+    /// Lines invalidated above because an inclusive level evicted them, by the evicting level
+    pub back_invalidations: Vec<u64>,
+    /// Hits in an exclusive level, which swap the line with the victim from the level above
+    pub exclusive_swaps: Vec<u64>,
+}
+
+impl InclusionStats {
+    pub fn new(levels: usize) -> Self {
+        InclusionStats { back_invalidations: vec![0; levels], exclusive_swaps: vec![0; levels] }
+    }
+}
+
+/// Coherence traffic, either of one core or on one line.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct CoherenceCounts {
+    /// Copies invalidated because another core wrote the line
+    pub invalidations: u64,
+    /// Copies that handed their data over, or wrote it back, for another core's request
+    pub interventions: u64,
+    /// Writes to a shared copy, which first had to invalidate the others
+    pub upgrades: u64,
+    /// Misses on a line that was lost to an invalidation (rather than evicted)
+    pub coherence_misses: u64,
+}
+
+impl CoherenceCounts {
+    fn total(&self) -> u64 {
+        self.invalidations + self.interventions + self.upgrades + self.coherence_misses
+    }
+}
+
+/// One core's view of its private data cache.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct CoreStats {
+    pub dc: QueryCounter,
+    pub counts: CoherenceCounts,
+}
+
+/// What it took to keep the DCs coherent, for comparing protocols.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct CoherenceTraffic {
+    /// Read misses, write misses and upgrades that had to ask about the other cores' copies
+    pub requests: u64,
+    /// Tag lookups in other cores' DCs to snoop the requests (or directory lookups instead)
+    pub lookups: u64,
+    /// Requests, snoops, forwards, invalidations and their replies sent between the caches
+    /// (and the directory)
+    pub messages: u64,
+    /// Lines handed straight from one core's DC to another's
+    pub transfers: u64,
+}
+
+/// Coherence traffic between the cores' data caches. Misses still count as plain misses in the
+/// DC's statistics; these say why some of them happened, and what they cost the other cores.
+#[derive(Default, Clone, Debug)]
+pub struct CoherenceStats {
+    pub cores: Vec<CoreStats>,
+    /// Keyed by the line's physical address, for lines that saw any coherence traffic
+    pub lines: BTreeMap<u64, CoherenceCounts>,
+    /// Whether lookups went to a directory rather than the other cores' tags
+    pub directory: bool,
+    pub traffic: CoherenceTraffic,
+}
+
+impl CoherenceStats {
+    pub fn new(cores: usize, directory: bool) -> Self {
+        CoherenceStats { cores: vec![CoreStats::default(); cores], directory, ..Default::default() }
+    }
+
+    /// Counts an event against both the core it happened to and the line it happened on.
+    pub fn count(&mut self, core: usize, line: u64, event: impl Fn(&mut CoherenceCounts)) {
+        event(&mut self.cores[core].counts);
+        event(self.lines.entry(line).or_default());
+    }
+
+    /// The lines with the most coherence traffic, busiest first.
+    pub fn busiest_lines(&self, n: usize) -> Vec<(u64, CoherenceCounts)> {
+        let mut lines: Vec<_> = self.lines.iter().map(|(&line, &counts)| (line, counts)).collect();
+        lines.sort_by_key(|(line, counts)| (std::cmp::Reverse(counts.total()), *line));
+        lines.truncate(n);
+        lines
+    }
+}
+
+/// How one process's references were translated.
+#[derive(Default, Copy, Clone, Debug)]
+pub struct ProcessCounts {
+    pub refs: u64,
+    pub dtlb: QueryCounter,
+    pub pt: QueryCounter,
+}
+
+/// Context switches, and each process's share of the translation work.
+#[derive(Default, Clone, Debug)]
+pub struct ProcessStats {
+    pub switches: u64,
+    /// Switches that flushed the TLBs (rather than leaving the entries tagged with their ASID)
+    pub flushes: u64,
+    /// Keyed by process ID, for every process that ran
+    pub processes: BTreeMap<u32, ProcessCounts>,
+}
+
+fn ratio(n: u64, d: u64) -> Option<f64> {
+    if d == 0 {
+        None
+    } else {
+        Some(n as f64 / d as f64)
+    }
+}
+
+fn fmt_ratio(r: Option<f64>) -> String {
+    r.map_or("N/A".to_string(), |r| format!("{:.6}", r))
+}
+
+/// How many lines the per-line coherence table lists
+const BUSIEST_LINES: usize = 8;
+
+/// What a cache level is called in the statistics: "dc" for the first level, then "L2", ...
+fn level_name(level: usize) -> String {
+    if level == 0 {
+        "dc".to_string()
+    } else {
+        format!("L{}", level + 1)
+    }
+}
+
+/// Running totals for a simulation, printed in the reference layout after the access table.
+#[derive(Default, Clone, Debug)]
+pub struct SimulationStats {
+    pub dtlb: QueryCounter,
+    /// Probes of the second-level TLB, if there is one
+    pub stlb: Option<QueryCounter>,
+    pub pt: QueryCounter,
+    /// One per configured cache level, closest to the CPU first
+    pub caches: Vec<QueryCounter>,
+    /// Probes of the DC's victim cache, if it has one
+    pub victim: Option<QueryCounter>,
+
+    pub reads: u64,
+    pub writes: u64,
+
+    pub main_memory_refs: u64,
+    pub page_table_refs: u64,
+    /// Walks of a radix page table, if there is one
+    pub walk: Option<WalkStats>,
+    /// Only printed when there are huge pages
+    pub huge_pages: Option<HugePageStats>,
+    pub disk_refs: u64,
+
+    /// One per configured cache level, for levels with a dueling replacement policy
+    pub duels: Vec<Option<DuelStats>>,
+    /// One per configured cache level, for levels with a prefetcher
+    pub prefetches: Vec<Option<PrefetchStats>>,
+
+    /// Only printed when writeback reporting is turned on
+    pub writebacks: Option<Writebacks>,
+    /// Only printed when an inclusion policy is configured
+    pub inclusion: Option<InclusionStats>,
+    /// Only printed when there is more than one core
+    pub coherence: Option<CoherenceStats>,
+    /// Only printed once the trace switches context
+    pub processes: Option<ProcessStats>,
+    /// Only printed when accesses are timed
+    pub latency: Option<Latency>,
+    /// Only printed when main memory is modeled as DRAM
+    pub dram: Option<DramStats>,
+    /// Only printed when swap space is modeled
+    pub swap: Option<SwapStats>,
+    /// Only printed when the DC has a write buffer
+    pub write_buffer: Option<WriteBufferStats>,
+}
+
+impl SimulationStats {
+    pub fn new(levels: usize) -> Self {
+        SimulationStats {
+            caches: vec![QueryCounter::default(); levels],
+            duels: vec![None; levels],
+            prefetches: vec![None; levels],
+            writebacks: Some(Writebacks::new(levels)),
+            inclusion: Some(InclusionStats::new(levels)),
+            processes: Some(ProcessStats::default()),
+            ..Default::default()
+        }
+    }
+
+    pub fn read_ratio(&self) -> Option<f64> {
+        ratio(self.reads, self.reads + self.writes)
+    }
+
+    /// Average cycles per access spent on `cycles`.
+    fn per_access(&self, cycles: u64) -> Option<f64> {
+        ratio(cycles, self.reads + self.writes)
+    }
+}
+
+impl std::fmt::Display for SimulationStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "Simulation statistics")?;
+        writeln!(f)?;
+
+        writeln!(f, "dtlb hits        : {}", self.dtlb.hits)?;
+        writeln!(f, "dtlb misses      : {}", self.dtlb.misses)?;
+        writeln!(f, "dtlb hit ratio   : {}", fmt_ratio(self.dtlb.hit_ratio()))?;
+        writeln!(f)?;
+
+        if let Some(stlb) = &self.stlb {
+            writeln!(f, "stlb hits        : {}", stlb.hits)?;
+            writeln!(f, "stlb misses      : {}", stlb.misses)?;
+            writeln!(f, "stlb hit ratio   : {}", fmt_ratio(stlb.hit_ratio()))?;
+            writeln!(f)?;
+        }
+
+        writeln!(f, "pt hits          : {}", self.pt.hits)?;
+        writeln!(f, "pt faults        : {}", self.pt.misses)?;
+        writeln!(f, "pt hit ratio     : {}", fmt_ratio(self.pt.hit_ratio()))?;
+        writeln!(f)?;
+
+        if let Some(huge) = &self.huge_pages {
+            for (&size, refs) in huge.sizes.iter().zip(huge.refs.iter()) {
+                writeln!(f, "{:<17}: {}", format!("{} page refs", bits::size_str(size)), refs)?;
+            }
+            writeln!(f, "promotions       : {}", huge.promotions)?;
+            writeln!(f)?;
+        }
+
+        if let Some(walk) = &self.walk {
+            for (level, counter) in walk.caches.iter().enumerate() {
+                if let Some(counter) = counter {
+                    let name = format!("pwc{}", level + 1);
+                    writeln!(f, "{:<17}: {}", format!("{} hits", name), counter.hits)?;
+                    writeln!(f, "{:<17}: {}", format!("{} misses", name), counter.misses)?;
+                    writeln!(f, "{:<17}: {}", format!("{} hit ratio", name), fmt_ratio(counter.hit_ratio()))?;
+                    writeln!(f)?;
+                }
+            }
+        }
+
+        for (level, cache) in self.caches.iter().enumerate() {
+            let name = level_name(level);
+            writeln!(f, "{:<17}: {}", format!("{} hits", name), cache.hits)?;
+            writeln!(f, "{:<17}: {}", format!("{} misses", name), cache.misses)?;
+            writeln!(f, "{:<17}: {}", format!("{} hit ratio", name), fmt_ratio(cache.hit_ratio()))?;
+            writeln!(f)?;
+            if let (0, Some(victim)) = (level, &self.victim) {
+                writeln!(f, "vc hits          : {}", victim.hits)?;
+                writeln!(f, "vc misses        : {}", victim.misses)?;
+                writeln!(f, "vc hit ratio     : {}", fmt_ratio(victim.hit_ratio()))?;
+                writeln!(f)?;
+            }
+            if let (0, Some(buffer)) = (level, &self.write_buffer) {
+                writeln!(f, "wbuf writes      : {}", buffer.writes)?;
+                writeln!(f, "wbuf coalesced   : {}", buffer.coalesced)?;
+                writeln!(f, "coalescing rate  : {}", fmt_ratio(buffer.coalescing_rate()))?;
+                writeln!(f, "wbuf full stalls : {}", buffer.stalls)?;
+                writeln!(f, "wbuf forwards    : {}", buffer.forwarded)?;
+                writeln!(f, "wbuf drains      : {}", buffer.drained)?;
+                writeln!(f, "write reduction  : {}", fmt_ratio(buffer.write_reduction()))?;
+                writeln!(f)?;
+            }
+        }
+
+        writeln!(f, "Total reads      : {}", self.reads)?;
+        writeln!(f, "Total writes     : {}", self.writes)?;
+        writeln!(f, "Ratio of reads   : {}", fmt_ratio(self.read_ratio()))?;
+        writeln!(f)?;
+
+        writeln!(f, "main memory refs : {}", self.main_memory_refs)?;
+        writeln!(f, "page table refs  : {}", self.page_table_refs)?;
+        if let Some(walk) = &self.walk {
+            writeln!(f, "pte reads        : {}", walk.pte_reads)?;
+            writeln!(f, "refs per TLB miss: {}", fmt_ratio(walk.refs_per_walk()))?;
+        }
+        write!(f, "disk refs        : {}", self.disk_refs)?;
+        if let Some(swap) = &self.swap {
+            writeln!(f)?;
+            writeln!(f, "minor faults     : {}", swap.minor_faults)?;
+            writeln!(f, "major faults     : {}", swap.major_faults)?;
+            writeln!(f, "disk reads       : {}", swap.disk_reads)?;
+            writeln!(f, "disk writes      : {}", swap.disk_writes)?;
+            write!(f, "swap pages used  : {} of {}", swap.used, swap.capacity)?;
+        }
+
+        for (level, duel) in self.duels.iter().enumerate() {
+            if let Some(duel) = duel {
+                let name = level_name(level);
+                writeln!(f)?;
+                writeln!(f)?;
+                writeln!(f, "{:<17}: {}", format!("{} SRRIP wins", name), duel.srrip_wins)?;
+                write!(f, "{:<17}: {}", format!("{} BRRIP wins", name), duel.brrip_wins)?;
+            }
+        }
+
+        for (level, prefetch) in self.prefetches.iter().enumerate() {
+            if let Some(prefetch) = prefetch {
+                let name = level_name(level);
+                writeln!(f)?;
+                writeln!(f)?;
+                writeln!(f, "{:<17}: {}", format!("{} prefetches", name), prefetch.issued)?;
+                writeln!(f, "{:<17}: {}", format!("{} useful pf.", name), prefetch.useful)?;
+                writeln!(f, "{:<17}: {}", format!("{} unused evicted", name), prefetch.unused_evicted)?;
+                writeln!(f, "{:<17}: {}", format!("{} pf. accuracy", name), fmt_ratio(prefetch.accuracy()))?;
+                write!(f, "{:<17}: {}", format!("{} pf. coverage", name),
+                    fmt_ratio(prefetch.coverage(self.caches[level].misses)))?;
+            }
+        }
+
+        if let Some(wb) = &self.writebacks {
+            writeln!(f)?;
+            for (level, n) in wb.caches.iter().enumerate() {
+                writeln!(f)?;
+                write!(f, "{:<17}: {}", format!("{} writebacks", level_name(level)), n)?;
+            }
+            writeln!(f)?;
+            write!(f, "disk writes      : {}", wb.disk)?;
+        }
+
+        if let Some(inclusion) = &self.inclusion {
+            writeln!(f)?;
+            // The DC has nothing above it, so it can't back-invalidate or swap
+            for level in 1..inclusion.back_invalidations.len() {
+                let name = level_name(level);
+                writeln!(f)?;
+                writeln!(f, "{:<17}: {}", format!("{} back invals", name), inclusion.back_invalidations[level])?;
+                write!(f, "{:<17}: {}", format!("{} excl. swaps", name), inclusion.exclusive_swaps[level])?;
+            }
+        }
+
+        if let Some(coherence) = &self.coherence {
+            for (core, stats) in coherence.cores.iter().enumerate() {
+                let name = format!("c{}", core);
+                writeln!(f)?;
+                writeln!(f)?;
+                writeln!(f, "{:<17}: {}", format!("{} dc hits", name), stats.dc.hits)?;
+                writeln!(f, "{:<17}: {}", format!("{} dc misses", name), stats.dc.misses)?;
+                writeln!(f, "{:<17}: {}", format!("{} invalidations", name), stats.counts.invalidations)?;
+                writeln!(f, "{:<17}: {}", format!("{} interventions", name), stats.counts.interventions)?;
+                writeln!(f, "{:<17}: {}", format!("{} upgrades", name), stats.counts.upgrades)?;
+                write!(f, "{:<17}: {}", format!("{} coh. misses", name), stats.counts.coherence_misses)?;
+            }
+            let traffic = &coherence.traffic;
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "coh. requests    : {}", traffic.requests)?;
+            if coherence.directory {
+                writeln!(f, "directory lookups: {}", traffic.lookups)?;
+            } else {
+                writeln!(f, "snoop lookups    : {}", traffic.lookups)?;
+            }
+            writeln!(f, "coh. messages    : {}", traffic.messages)?;
+            write!(f, "c2c transfers    : {}", traffic.transfers)?;
+            writeln!(f)?;
+            writeln!(f)?;
+            write!(f, "busiest lines    :   invals  interv. upgrades  c.misses")?;
+            for (line, counts) in coherence.busiest_lines(BUSIEST_LINES) {
+                writeln!(f)?;
+                write!(f, "{:<17}: {:>8} {:>8} {:>8} {:>9}", format!("{:08x}", line),
+                    counts.invalidations, counts.interventions, counts.upgrades, counts.coherence_misses)?;
+            }
+        }
+
+        if let Some(latency) = &self.latency {
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "total cycles     : {}", latency.total())?;
+            writeln!(f, "AMAT             : {}", fmt_ratio(self.per_access(latency.total())))?;
+            writeln!(f, "tlb AMAT         : {}", fmt_ratio(self.per_access(latency.tlb)))?;
+            if self.stlb.is_some() {
+                writeln!(f, "stlb AMAT        : {}", fmt_ratio(self.per_access(latency.stlb)))?;
+            }
+            writeln!(f, "walk AMAT        : {}", fmt_ratio(self.per_access(latency.walk)))?;
+            for (level, &cycles) in latency.caches.iter().enumerate() {
+                writeln!(f, "{:<17}: {}", format!("{} AMAT", level_name(level)), fmt_ratio(self.per_access(cycles)))?;
+            }
+            writeln!(f, "memory AMAT      : {}", fmt_ratio(self.per_access(latency.memory)))?;
+            write!(f, "disk AMAT        : {}", fmt_ratio(self.per_access(latency.disk)))?;
+        }
+
+        if let Some(dram) = &self.dram {
+            let total = dram.total();
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "dram requests    : {}", total.requests)?;
+            writeln!(f, "dram row hits    : {}", total.row_hits)?;
+            writeln!(f, "dram row empty   : {}", total.row_empty)?;
+            writeln!(f, "dram conflicts   : {}", total.row_conflicts)?;
+            writeln!(f, "dram row hit rate: {}", fmt_ratio(ratio(total.row_hits, total.requests)))?;
+            writeln!(f, "dram busy cycles : {}", total.busy)?;
+            writeln!(f)?;
+            write!(f, "dram banks       : requests row hits    empty conflicts    util.")?;
+            for (bank, counts) in dram.banks.iter().enumerate() {
+                writeln!(f)?;
+                write!(f, "{:<17}: {:>8} {:>8} {:>8} {:>9} {:>8}", dram.bank_name(bank),
+                    counts.requests, counts.row_hits, counts.row_empty, counts.row_conflicts,
+                    ratio(counts.busy, total.busy).map_or("N/A".to_string(), |r| format!("{:.4}", r)))?;
+            }
+        }
+
+        if let Some(processes) = &self.processes {
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "context switches : {}", processes.switches)?;
+            write!(f, "tlb flushes      : {}", processes.flushes)?;
+            for (pid, counts) in processes.processes.iter() {
+                let name = format!("p{}", pid);
+                writeln!(f)?;
+                writeln!(f)?;
+                writeln!(f, "{:<17}: {}", format!("{} refs", name), counts.refs)?;
+                writeln!(f, "{:<17}: {}", format!("{} dtlb hits", name), counts.dtlb.hits)?;
+                writeln!(f, "{:<17}: {}", format!("{} dtlb misses", name), counts.dtlb.misses)?;
+                writeln!(f, "{:<17}: {}", format!("{} pt hits", name), counts.pt.hits)?;
+                write!(f, "{:<17}: {}", format!("{} pt faults", name), counts.pt.misses)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Miss counts of a normal run next to those of a replay with Belady's OPT replacement.
+pub struct OptComparison {
+    pub actual: SimulationStats,
+    pub opt: SimulationStats,
+}
+
+impl std::fmt::Display for OptComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f)?;
+        writeln!(f, "OPT comparison   :   actual      OPT")?;
+        writeln!(f, "dtlb misses      : {:>8} {:>8}", self.actual.dtlb.misses, self.opt.dtlb.misses)?;
+        for (level, (actual, opt)) in self.actual.caches.iter().zip(self.opt.caches.iter()).enumerate() {
+            writeln!(f, "{:<17}: {:>8} {:>8}", format!("{} misses", level_name(level)), actual.misses, opt.misses)?;
+        }
+        write!(f, "main memory refs : {:>8} {:>8}", self.actual.main_memory_refs, self.opt.main_memory_refs)
+    }
+}
+
+/// Coherence traffic of the same trace under each protocol.
+pub struct ProtocolComparison {
+    /// The name of each protocol, and the statistics of a run with it
+    pub runs: Vec<(String, SimulationStats)>,
+}
+
+impl std::fmt::Display for ProtocolComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f)?;
+        write!(f, "{:<17}: {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "protocol", "requests", "lookups", "messages", "transfers", "mem refs")?;
+        for (name, stats) in self.runs.iter() {
+            let traffic = stats.coherence.as_ref().map(|c| c.traffic).unwrap_or_default();
+            writeln!(f)?;
+            write!(f, "{:<17}: {:>9} {:>9} {:>9} {:>9} {:>9}", name,
+                traffic.requests, traffic.lookups, traffic.messages, traffic.transfers, stats.main_memory_refs)?;
+        }
+        Ok(())
+    }
+}
@@ -1,141 +1,152 @@
 use crate::{
     config,
     utils::bits,
-    memory::QueryResult,
+    memory::{
+        QueryResult,
+        replacement::{self, ReplacementPolicy},
+    },
 };
 
 
 pub struct TLBResponse {
-    pub vpn: u32,
-    pub tag: u32,
-    pub idx: u32,
+    pub vpn: u64,
+    pub tag: u64,
+    pub idx: u64,
     pub result: QueryResult,
-    pub ppn: Option<u32>,
-    pub page_offset: u32,
+    pub ppn: Option<u64>,
+    pub page_offset: u64,
+    /// log2 of the base pages covered by the entry that hit (0 on a miss)
+    pub page_shift: u32,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct TLBEntry {
-    tag: u32,
-    vpn: u32,
-    ppn: u32,
+    tag: u64,
+    /// Address space the translation belongs to
+    asid: u32,
+    /// First vpn and ppn of the page, which is `1 << shift` base pages long
+    vpn: u64,
+    ppn: u64,
+    shift: u32,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct TLB {
-    sets: Vec<LRUSet>,
+    sets: Vec<Vec<Option<TLBEntry>>>,
+    policy: Box<dyn ReplacementPolicy>,
+    /// Every page size (as a shift of the base page size) the TLB has been given an entry for.
+    /// Pages of each size are indexed by their own page number, so a lookup probes each size.
+    shifts: Vec<u32>,
+    /// Address space of the running process: only its entries hit, and new entries get it
+    asid: u32,
     config: config::TLBConfig,
 }
 
+/// The key a translation is known by to the replacement policy (and so to the OPT oracle): the
+/// first vpn of its page, with the ASID in the bits above the widest vpn OPT allows. Every vpn in
+/// a huge page gets the same key.
+pub fn translation_key(asid: u32, vpn: u64) -> u64 {
+    vpn ^ ((asid as u64) << config::MAX_OPT_VPN_BITS)
+}
+
 impl TLB {
     pub fn new(config: config::TLBConfig) -> Self {
-        let empty_set = LRUSet::new(config.set_entries as usize);
+        let policy = replacement::new_policy(
+            config.replacement, config.sets as usize, config.set_entries as usize);
+        TLB::with_policy(config, policy)
+    }
+
+    /// Builds a TLB around a replacement policy that can't be described by the config alone.
+    pub fn with_policy(config: config::TLBConfig, policy: Box<dyn ReplacementPolicy>) -> Self {
+        let empty_set = vec![ None ; config.set_entries as usize ];
         let sets = vec![ empty_set ; config.sets as usize ];
-        TLB { sets, config, }
+        TLB { sets, policy, shifts: vec![0], asid: 0, config, }
+    }
+
+    /// Switches to the address space entries are looked up and added in.
+    pub fn set_asid(&mut self, asid: u32) {
+        self.asid = asid;
+    }
+
+    /// Drops every entry, of every address space.
+    pub fn flush(&mut self) {
+        for (set, entries) in self.sets.iter_mut().enumerate() {
+            for (way, entry) in entries.iter_mut().enumerate() {
+                if entry.take().is_some() {
+                    self.policy.invalidate(set, way);
+                }
+            }
+        }
     }
 
     /// Looks up vpn in TLB for a fast translation.
-    pub fn lookup(&mut self, addr: u32) -> TLBResponse {
+    pub fn lookup(&mut self, addr: u64) -> TLBResponse {
         let (vpn, page_offset) = bits::split_at(addr, self.config.offset_size);
-        let (tag, idx) = bits::split_at(vpn, self.config.idx_size);
 
-        let set = &mut self.sets[idx as usize];
-
-        let (ppn, result) = match set.lookup(tag) {
-            Some(entry) => (Some(entry.ppn), QueryResult::Hit),
-            None => (None, QueryResult::Miss),
-        };
+        for &shift in self.shifts.iter() {
+            let (tag, idx) = bits::split_at(vpn >> shift, self.config.idx_size);
+            let set = idx as usize;
+            let way = self.sets[set]
+                .iter()
+                .position(|entry| matches!(entry, Some(e) if e.tag == tag && e.shift == shift && e.asid == self.asid));
+
+            if let Some(way) = way {
+                self.policy.touch(set, way);
+                return TLBResponse {
+                    vpn,
+                    tag,
+                    idx,
+                    ppn: self.sets[set][way].map(|e| e.ppn + (vpn - e.vpn)),
+                    page_offset,
+                    result: QueryResult::Hit,
+                    page_shift: shift,
+                };
+            }
+        }
 
+        let (tag, idx) = bits::split_at(vpn, self.config.idx_size);
         TLBResponse { 
             vpn,
             tag, 
             idx, 
-            ppn,
+            ppn: None,
             page_offset,
-            result,
+            result: QueryResult::Miss,
+            page_shift: 0,
         }
     }
 
-    /// Add a vpn-ppn translation to the TLB
-    pub fn push(&mut self, vpn: u32, ppn: u32) {
-        let (tag, idx) = bits::split_at(vpn, self.config.idx_size);
-        let entry = TLBEntry { tag, vpn, ppn, };
-
-        self.sets[idx as usize].push(entry);
-    }
-
-    /// Removes all references to translations with the corresponding ppn
-    pub fn clean_ppn(&mut self, ppn: u32) {
-        for set in self.sets.iter_mut() {
-            set.invalidate_entries(ppn);
+    /// Add a vpn-ppn translation to the TLB, for a page `1 << shift` base pages long
+    pub fn push(&mut self, vpn: u64, ppn: u64, shift: u32) {
+        let (tag, idx) = bits::split_at(vpn >> shift, self.config.idx_size);
+        let offset = vpn & ((1 << shift) - 1);
+        let entry = TLBEntry { tag, asid: self.asid, vpn: vpn - offset, ppn: ppn - offset, shift };
+        let key = translation_key(self.asid, entry.vpn);
+        if let Err(i) = self.shifts.binary_search(&shift) {
+            self.shifts.insert(i, shift);
         }
-    }
-}
-
-/* === LRU Set === */
-
-use std::collections::VecDeque;
-
-/// A simple LRU Set which evicts elements upon insertion such that the set never exceeds `capacity`
-/// This one is tailored for use with the CPUCache. 
-// 
-// DO NOT GENERALIZE THIS AGAIN, PLEASE.
-//
-//  This could be faster. By using a VecDequeue, 'touching' an item of the cache is O(n). Ideally,
-//  some kind of linked hash map could be used for O(1) lookup _and_ O(1) touch. I suspect that
-//  designing that from scratch would have some serious rust shenanigans that I don't want to deal with.
-//  Also, the max set size is like, 16. Just a note for future reference.
-#[derive(Clone, Debug)]
-struct LRUSet {
-    inner: VecDeque<TLBEntry>,
-    capacity: usize,
-}
 
-impl LRUSet {
-    fn new(capacity: usize) -> Self {
-        let inner = VecDeque::<TLBEntry>::with_capacity(capacity);
-        LRUSet { inner, capacity }
-    }
-
-    /// Push an item to the LRU Set, potentially evicting the oldest item
-    fn push(&mut self, entry: TLBEntry) -> Option<TLBEntry> {
-        let evicted_item = if self.inner.len() >= self.capacity {
-            self.inner.pop_back()
-        } else { None };
-        self.inner.push_front(entry);
-
-        evicted_item
+        let set = idx as usize;
+        let way = match self.sets[set].iter().position(|e| e.is_none()) {
+            Some(way) => way,
+            None => self.policy.victim(set),
+        };
+        self.sets[set][way] = Some(entry);
+        self.policy.place(set, way, key);
+        self.policy.fill(set, way);
     }
 
-    /// Look up an item in the LRU Set. If found, the item is 'touched' and moved to the front
-    /// of the queue.
-    fn lookup(&mut self, tag: u32) -> Option<TLBEntry> {
-        let item_search = self.inner
-            .iter()
-            .position(|entry| entry.tag == tag );
-        
-        if let Some(item_idx) = item_search {
-            let item = self.inner.remove(item_idx).unwrap();
-            self.inner.push_front(item.clone());
-            Some(item)
-        } else {
-            None
+    /// Removes all references to translations with the corresponding ppn
+    pub fn clean_ppn(&mut self, ppn: u64) {
+        for (set, entries) in self.sets.iter_mut().enumerate() {
+            for (way, entry) in entries.iter_mut().enumerate() {
+                if matches!(entry, Some(e) if (e.ppn..e.ppn + (1 << e.shift)).contains(&ppn)) {
+                    *entry = None;
+                    self.policy.invalidate(set, way);
+                }
+            }
         }
     }
-
-    /// Evicts any entry that corresponds to the supplied ppn.
-    fn invalidate_entries(&mut self, ppn: u32) {
-        // Copy the entires LRU set, removing the invalid entries
-        let new_inner = self.inner
-            .iter()
-            // filter out invalid entries
-            .filter(|entry| entry.ppn != ppn)
-            // take raw entries and box them up for shipping
-            .map(|entry| entry.clone())
-            .collect();
-        
-        // Set the LRUSet's inner to be the filtered set
-        self.inner = new_inner;
-    }
 }
 
 impl std::fmt::Debug for TLB {
@@ -143,11 +154,11 @@ impl std::fmt::Debug for TLB {
         writeln!(f, "TLB:")?;
         for (idx, set) in self.sets.iter().enumerate() {
             writeln!(f, "\tSet {:x}:", idx)?;
-            for e in set.inner.iter() {
-                writeln!(f, "\t\tvpn: {:x}\n\t\ttag: {:x}\n\t\tppn: {:x}",
-                    e.vpn, e.tag, e.ppn, )?;
+            for e in set.iter().flatten() {
+                writeln!(f, "\t\tasid: {}\n\t\tvpn: {:x}\n\t\ttag: {:x}\n\t\tppn: {:x}",
+                    e.asid, e.vpn, e.tag, e.ppn, )?;
             }
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
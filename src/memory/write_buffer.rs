@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+
+use crate::{
+    config::WriteBufferConfig,
+    utils::bits,
+};
+
+/// Lines written through the DC on their way to the level below, oldest first. Each holds every
+/// write made to its line since it was buffered.
+#[derive(Debug)]
+pub struct WriteBuffer {
+    /// Line addresses (the byte address of each line's start)
+    lines: VecDeque<u64>,
+    /// log2 of the DC's line size, which is what writes are combined by
+    offset_size: u32,
+    pub config: WriteBufferConfig,
+}
+
+impl WriteBuffer {
+    pub fn new(config: WriteBufferConfig, offset_size: u32) -> Self {
+        WriteBuffer { lines: VecDeque::with_capacity(config.entries as usize), offset_size, config }
+    }
+
+    fn line(&self, addr: u64) -> u64 {
+        let (block, _block_offset) = bits::split_at(addr, self.offset_size);
+        bits::join_at(block, 0, self.offset_size)
+    }
+
+    /// Whether a write to `addr`'s line is waiting, so a read can be forwarded from it
+    pub fn holds(&self, addr: u64) -> bool {
+        self.lines.contains(&self.line(addr))
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.lines.len() >= self.config.entries as usize
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Buffers a write. Returns true if it was combined with one already waiting; otherwise
+    /// there must be room for it.
+    pub fn push(&mut self, addr: u64) -> bool {
+        let line = self.line(addr);
+        if self.lines.contains(&line) {
+            return true;
+        }
+        debug_assert!(!self.is_full());
+        self.lines.push_back(line);
+        false
+    }
+
+    /// Takes the oldest line, to be written below
+    pub fn pop(&mut self) -> Option<u64> {
+        self.lines.pop_front()
+    }
+
+    /// Takes every line in the page `ppn`, oldest first, so they reach memory before the page
+    /// leaves it.
+    pub fn take_page(&mut self, ppn: u64, page_offset_size: u32) -> Vec<u64> {
+        let (taken, kept): (VecDeque<u64>, VecDeque<u64>) = self.lines.iter()
+            .partition(|&&line| bits::split_at(line, page_offset_size).0 == ppn);
+        self.lines = kept;
+        taken.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DrainPolicy;
+
+    /// A 3-line buffer of 16B lines
+    fn write_buffer() -> WriteBuffer {
+        WriteBuffer::new(WriteBufferConfig { entries: 3, drain: DrainPolicy::Full }, 4)
+    }
+
+    #[test]
+    fn writes_to_a_waiting_line_are_combined() {
+        let mut wb = write_buffer();
+        assert!(!wb.push(0x104));
+        assert!(wb.push(0x10c));
+        assert!(!wb.push(0x110));
+        assert_eq!(wb.len(), 2);
+        assert!(wb.holds(0x100));
+        assert!(!wb.holds(0x120));
+    }
+
+    #[test]
+    fn lines_leave_oldest_first() {
+        let mut wb = write_buffer();
+        for addr in [0x230, 0x104, 0x238] {
+            wb.push(addr);
+        }
+        assert!(!wb.is_full());
+        wb.push(0x300);
+        assert!(wb.is_full());
+        assert_eq!(wb.pop(), Some(0x230));
+        assert_eq!(wb.pop(), Some(0x100));
+        assert_eq!(wb.pop(), Some(0x300));
+        assert_eq!(wb.pop(), None);
+    }
+
+    #[test]
+    fn take_page_takes_only_that_page() {
+        let mut wb = write_buffer();
+        for addr in [0x1230, 0x2010, 0x1f00] {
+            wb.push(addr);
+        }
+        // 4KB pages
+        assert_eq!(wb.take_page(1, 12), vec![0x1230, 0x1f00]);
+        assert_eq!(wb.len(), 1);
+        assert!(wb.holds(0x2018));
+        assert_eq!(wb.take_page(1, 12), Vec::<u64>::new());
+    }
+}
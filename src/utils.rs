@@ -14,26 +14,67 @@ pub mod bits {
     }
 
     /// Calculates the minimum number of bits required to represent a number n.
-    pub fn min_repr(n: u32) -> u32 {
-        bit_size::<u32>() as u32 - n.leading_zeros() - 1
+    pub fn min_repr(n: impl Into<u64>) -> u32 {
+        bit_size::<u64>() as u32 - n.into().leading_zeros() - 1
     }
 
     /// Checks if a number is a power of 2.
-    pub fn is_pow2(n: u32) -> bool {
-        n.count_ones() == 1
+    pub fn is_pow2(n: impl Into<u64>) -> bool {
+        n.into().count_ones() == 1
     }
 
-    /// Splits a u32 into two u32s at the bit index.
-    pub fn split_at(x: u32, n: u32) -> (u32, u32) {
+    /// Splits a u64 into two u64s at the bit index.
+    pub fn split_at(x: u64, n: u32) -> (u64, u64) {
         let mask = (1 << n) - 1;
         let right = x & mask;
-        let left = ( x &! mask) >> n;
+        let left = (x & !mask) >> n;
         (left, right)
     }
 
-    /// Joins two u32s together at the bit index.
-    pub fn join_at(x: u32, y: u32, n: u32) -> u32 {
+    /// Joins two u64s together at the bit index.
+    pub fn join_at(x: u64, y: u64, n: u32) -> u64 {
         let x_shifted = x << n;
         x_shifted | y 
     }
-}
\ No newline at end of file
+
+    /// Writes a power-of-2 byte count the short way ("4K", "2M", "1G").
+    pub fn size_str(bytes: u64) -> String {
+        match bytes.trailing_zeros() {
+            30.. => format!("{}G", bytes >> 30),
+            20.. => format!("{}M", bytes >> 20),
+            10.. => format!("{}K", bytes >> 10),
+            _ => bytes.to_string(),
+        }
+    }
+}
+
+/// A tiny seeded PRNG so that "random" simulations are reproducible (and we stay dependency free).
+pub mod rng {
+    /// xorshift64* generator.
+    #[derive(Copy, Clone, Debug)]
+    pub struct XorShift64 {
+        state: u64,
+    }
+
+    impl XorShift64 {
+        pub fn new(seed: u64) -> Self {
+            // xorshift gets stuck at zero forever, so nudge it off
+            let state = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+            XorShift64 { state }
+        }
+
+        pub fn next_u64(&mut self) -> u64 {
+            let mut x = self.state;
+            x ^= x >> 12;
+            x ^= x << 25;
+            x ^= x >> 27;
+            self.state = x;
+            x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        /// Produces a number in `0..n`.
+        pub fn below(&mut self, n: u64) -> u64 {
+            self.next_u64() % n
+        }
+    }
+}
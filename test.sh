@@ -3,7 +3,9 @@
 cat > ./.tmp_trace.dat
 trace_file=./.tmp_trace.dat
 
-diff --color -w <(cat $trace_file | ../memhier/memhier_ref | head -n -26) <(cat $trace_file | RUSTFLAGS="-Awarnings" cargo run)
+# The reference ends with 26 lines of statistics, which memsim-rs prints in the same layout,
+# so whole outputs are compared
+diff --color -w <(cat $trace_file | ../memhier/memhier_ref) <(cat $trace_file | RUSTFLAGS="-Awarnings" cargo run)
 
 if [ $? -eq 0 ] 
 then